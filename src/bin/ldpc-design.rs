@@ -0,0 +1,58 @@
+use std::env;
+use std::error::Error;
+use std::process;
+
+use rustft8::ldpc::FTX_LDPC_CODE;
+use rustft8::peg::peg_code;
+use rustft8::tanner::TannerGraph;
+
+// PEGでLDPC符号を設計し、constant.rsと同じ形式のテーブルを出力する
+//   ldpc-design <N> <M> <degrees> [prefix]
+//   degrees: column degree distribution, e.g. "3" or "2:0.25,3:0.75"
+// 設計した符号のタナーグラフ解析(ガース、サイクル数、トラッピングセット)は標準エラーへ
+//   ldpc-design --analyze   解析のみ(FT8/FT4の(174,91)符号)
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_distribution(arg: &str) -> Result<Vec<(usize, f32)>, Box<dyn Error>> {
+    let mut distribution = Vec::new();
+    for item in arg.split(',') {
+        match item.split_once(':') {
+            Some((degree, fraction)) => distribution.push((degree.parse()?, fraction.parse()?)),
+            None => distribution.push((item.parse()?, 1.0)),
+        }
+    }
+    Ok(distribution)
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|a| a == "--analyze") {
+        print!("{}", TannerGraph::from_code(&FTX_LDPC_CODE).report());
+        return Ok(());
+    }
+    if args.len() < 4 {
+        println!("Usage: ldpc-design <N> <M> <degree[:fraction],...> [prefix] | --analyze");
+        return Ok(());
+    }
+    let n = args[1].parse::<usize>()?;
+    let m = args[2].parse::<usize>()?;
+    let distribution = parse_distribution(&args[3])?;
+    let prefix = args.get(4).map_or("LDPC", |p| p.as_str());
+
+    let code = peg_code(n, m, &distribution)?;
+    eprintln!(
+        "({},{}) code, {} checks, rank {}",
+        code.n,
+        code.k,
+        code.m(),
+        code.n - code.k
+    );
+    eprint!("{}", TannerGraph::from_code(&code).report());
+    print!("{}", code.to_tables(prefix));
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+use crate::constant::SNR_BANDWIDTH;
+
+const PI: f32 = std::f32::consts::PI;
+
+// Amplitude of a sine wave that has the given SNR in 2500 Hz
+// against white noise of unit variance (same as WSJT-X ft8sim)
+pub fn snr_amplitude(snr: f32, sample_rate: u32) -> f32 {
+    (2.0 * SNR_BANDWIDTH / (sample_rate as f32 / 2.0)).sqrt() * 10f32.powf(snr / 20.0)
+}
+
+// Two-path Watterson (CCIR 520) fading
+#[derive(Debug, Clone, Copy)]
+pub struct Fading {
+    pub delay: f32,          // Delay of the second path (s)
+    pub doppler_spread: f32, // Doppler spread of each path, two sigma (Hz)
+}
+
+impl Fading {
+    // CCIR 520 "good", "moderate" and "poor" channels
+    pub const GOOD: Fading = Fading {
+        delay: 0.0005,
+        doppler_spread: 0.1,
+    };
+    pub const MODERATE: Fading = Fading {
+        delay: 0.001,
+        doppler_spread: 0.5,
+    };
+    pub const POOR: Fading = Fading {
+        delay: 0.002,
+        doppler_spread: 1.0,
+    };
+}
+
+// 伝搬路のシミュレーション
+// synth_gfsk(やSlotComposer)の出力に時刻ずれ、周波数ドリフト、フェージング、雑音の順で劣化を加える
+pub struct Channel {
+    pub sample_rate: u32,
+    pub noise: bool,            // Add white Gaussian noise of unit variance
+    pub fading: Option<Fading>, // Watterson fading
+    pub drift: f32,             // Linear frequency drift (Hz/s), zero at the middle of the signal
+    pub clock_offset: f32,      // Time shift of the transmitter clock (s), positive is late
+    pub seed: u64,              // Seed of the noise and fading processes
+}
+
+impl Channel {
+    // AWGN only
+    pub fn awgn(sample_rate: u32) -> Self {
+        Channel {
+            sample_rate,
+            noise: true,
+            fading: None,
+            drift: 0.0,
+            clock_offset: 0.0,
+            seed: 1,
+        }
+    }
+
+    pub fn apply(&self, signal: &[f32]) -> Vec<f32> {
+        let fs = self.sample_rate as f32;
+        let mut rng = Rng::new(self.seed);
+
+        //送信側の時計のずれ(小数サンプルは線形補間)
+        let mut out = if self.clock_offset != 0.0 {
+            let shift = self.clock_offset * fs;
+            (0..signal.len())
+                .map(|n| {
+                    let x = n as f32 - shift;
+                    let i = x.floor();
+                    let frac = x - i;
+                    let at = |i: f32| {
+                        if i >= 0.0 && (i as usize) < signal.len() {
+                            signal[i as usize]
+                        } else {
+                            0.0
+                        }
+                    };
+                    at(i) * (1.0 - frac) + at(i + 1.0) * frac
+                })
+                .collect()
+        } else {
+            signal.to_vec()
+        };
+
+        //ドリフトとフェージングは解析信号(複素)で処理する
+        if self.drift != 0.0 || self.fading.is_some() {
+            let mut z = analytic(&out);
+            let t_mid = z.len() as f32 / fs / 2.0;
+
+            if self.drift != 0.0 {
+                //周波数が drift * (t - t_mid) ずれるように位相を回す
+                for (n, v) in z.iter_mut().enumerate() {
+                    let t = n as f32 / fs - t_mid;
+                    let phi = PI * self.drift * t * t;
+                    *v *= Complex::new(phi.cos(), phi.sin());
+                }
+            }
+
+            if let Some(fading) = self.fading {
+                //平均電力1/2の2つのパスを足し合わせる
+                let delay = (fading.delay * fs).round() as usize;
+                let g1 = fading_gain(z.len(), fs, fading.doppler_spread, &mut rng);
+                let g2 = fading_gain(z.len(), fs, fading.doppler_spread, &mut rng);
+                let faded: Vec<Complex<f32>> = (0..z.len())
+                    .map(|n| {
+                        let direct = z[n] * g1[n];
+                        let delayed = if n >= delay {
+                            z[n - delay] * g2[n]
+                        } else {
+                            Complex::new(0.0, 0.0)
+                        };
+                        (direct + delayed) * std::f32::consts::FRAC_1_SQRT_2
+                    })
+                    .collect();
+                z = faded;
+            }
+
+            out = z.iter().map(|v| v.re).collect();
+        }
+
+        if self.noise {
+            for v in out.iter_mut() {
+                *v += rng.gaussian();
+            }
+        }
+        out
+    }
+}
+
+// Analytic signal via FFT (negative frequencies removed)
+fn analytic(signal: &[f32]) -> Vec<Complex<f32>> {
+    let n = signal.len();
+    let mut planner = FftPlanner::<f32>::new();
+    let mut z: Vec<Complex<f32>> = signal.iter().map(|s| Complex::new(*s, 0.0)).collect();
+    planner.plan_fft_forward(n).process(&mut z);
+    for (k, v) in z.iter_mut().enumerate().skip(1) {
+        if 2 * k < n {
+            *v *= 2.0;
+        } else if 2 * k > n {
+            *v = Complex::new(0.0, 0.0);
+        }
+    }
+    planner.plan_fft_inverse(n).process(&mut z);
+    for v in z.iter_mut() {
+        *v /= n as f32;
+    }
+    z
+}
+
+// Complex Gaussian gain process with a Gaussian Doppler spectrum and unit mean power
+fn fading_gain(n: usize, fs: f32, doppler_spread: f32, rng: &mut Rng) -> Vec<Complex<f32>> {
+    let sigma = doppler_spread / 2.0;
+    if sigma <= 0.0 {
+        //ドップラー広がりがなければ位相が一定のパス
+        let phi = 2.0 * PI * rng.uniform();
+        return vec![Complex::new(phi.cos(), phi.sin()); n];
+    }
+
+    //周波数領域でガウス型のスペクトルを持つ雑音を作り逆FFTする
+    let mut g: Vec<Complex<f32>> = (0..n)
+        .map(|k| {
+            let f = if 2 * k < n {
+                k as f32
+            } else {
+                k as f32 - n as f32
+            } * fs
+                / n as f32;
+            let h = (-f * f / (4.0 * sigma * sigma)).exp();
+            Complex::new(rng.gaussian(), rng.gaussian()) * h
+        })
+        .collect();
+    FftPlanner::<f32>::new().plan_fft_inverse(n).process(&mut g);
+    let power = g.iter().map(|v| v.norm_sqr()).sum::<f32>() / n as f32;
+    let norm = 1.0 / power.sqrt();
+    for v in g.iter_mut() {
+        *v *= norm;
+    }
+    g
+}
+
+// xorshift64* (外部クレートを使わない簡易な乱数)
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    // Uniform in (0, 1]
+    fn uniform(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+    }
+
+    // Standard normal (Box-Muller)
+    fn gaussian(&mut self) -> f32 {
+        let r = (-2.0 * self.uniform().ln()).sqrt();
+        r * (2.0 * PI * self.uniform()).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composer::{SlotComposer, Transmission};
+    use crate::ft8decode::decode_slot;
+    use crate::monitor::Config;
+    use crate::protocol::FT8;
+
+    // Decoded messages of one FT8 slot at the given SNR
+    fn decode_at(snr: f32, channel: &Channel) -> Vec<String> {
+        let mut composer = SlotComposer::new(&FT8, channel.sample_rate);
+        composer.add(Transmission::new("CQ JA1ZLO PM95", 1200.0, 0.2, snr));
+        let samples = channel.apply(&composer.compose().unwrap());
+
+        let config = Config {
+            sample_rate: channel.sample_rate,
+            ..Config::default_for(&FT8)
+        };
+        decode_slot(&samples, &config)
+            .into_iter()
+            .map(|message| message.text)
+            .collect()
+    }
+
+    #[test]
+    fn noise_limits_sensitivity() {
+        let channel = Channel::awgn(12000);
+        assert_eq!(decode_at(-10.0, &channel), vec!["CQ JA1ZLO PM95"]);
+        assert!(decode_at(-30.0, &channel).is_empty());
+    }
+
+    fn tone(freq: f32, fs: u32, n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| (2.0 * PI * freq * i as f32 / fs as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn awgn_is_calibrated() {
+        let fs = 12000;
+        let amplitude = snr_amplitude(-10.0, fs);
+        let signal: Vec<f32> = tone(1000.0, fs, 120000)
+            .iter()
+            .map(|s| s * amplitude)
+            .collect();
+        let noisy = Channel::awgn(fs).apply(&signal);
+
+        let noise_power = noisy
+            .iter()
+            .zip(signal.iter())
+            .map(|(y, x)| (y - x) * (y - x))
+            .sum::<f32>()
+            / signal.len() as f32;
+        assert!((noise_power - 1.0).abs() < 0.02);
+
+        // Signal power against the noise power in 2500 Hz
+        let signal_power = amplitude * amplitude / 2.0;
+        let snr = 10.0 * (signal_power / (noise_power * SNR_BANDWIDTH / 6000.0)).log10();
+        assert!((snr + 10.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn drift_and_clock_offset() {
+        let fs = 12000;
+        let n = 24000;
+        let signal = tone(1000.0, fs, n);
+        let mut channel = Channel {
+            sample_rate: fs,
+            noise: false,
+            fading: None,
+            drift: 0.0,
+            clock_offset: 0.01,
+            seed: 1,
+        };
+        // 120 samples of silence, then the same tone
+        let out = channel.apply(&signal);
+        assert!(out[..120].iter().all(|v| *v == 0.0));
+        assert!((out[120..].iter().zip(signal.iter())).all(|(y, x)| (y - x).abs() < 1e-4));
+
+        // Count zero crossings in the first and last 0.5 s: -1.5 Hz and +1.5 Hz around 1000 Hz
+        channel.drift = 2.0;
+        channel.clock_offset = 0.0;
+        let out = channel.apply(&signal);
+        let crossings = |s: &[f32]| s.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        let first = crossings(&out[..6000]);
+        let last = crossings(&out[n - 6000..]);
+        assert!(last > first, "{} {}", first, last);
+    }
+
+    #[test]
+    fn fading_keeps_mean_power() {
+        let fs = 12000;
+        let signal = tone(1000.0, fs, 180000);
+        let channel = Channel {
+            sample_rate: fs,
+            noise: false,
+            fading: Some(Fading::POOR),
+            drift: 0.0,
+            clock_offset: 0.0,
+            seed: 7,
+        };
+        let out = channel.apply(&signal);
+        let power = out.iter().map(|v| v * v).sum::<f32>() / out.len() as f32;
+        assert!(power > 0.2 && power < 0.8, "{}", power);
+
+        // Fading changes the envelope over time
+        let block_power: Vec<f32> = out
+            .chunks(12000)
+            .map(|b| b.iter().map(|v| v * v).sum::<f32>() / b.len() as f32)
+            .collect();
+        let max = block_power.iter().cloned().fold(0.0, f32::max);
+        let min = block_power.iter().cloned().fold(f32::MAX, f32::min);
+        assert!(max > 2.0 * min);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::channel::snr_amplitude;
+use crate::constant::FTX_LDPC_K_BYTES;
+use crate::error::Ft8Error;
+use crate::ft8encode::ftx_encode;
+use crate::gfsk::synth_gfsk_drift;
+use crate::hashtable::CallsignHashTable;
+use crate::pack::pack77;
+use crate::protocol::Protocol;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+// One transmission in the slot
+#[derive(Debug, Clone)]
+pub struct Transmission {
+    pub message: String,
+    pub freq_hz: f32,       // Audio frequency of tone 0
+    pub time_offset: f32,   // DT (s)
+    pub snr: f32,           // SNR in 2500 Hz (dB)
+    pub drift: Option<f32>, // Frequency drift (Hz/s)
+}
+
+impl Transmission {
+    pub fn new(message: &str, freq_hz: f32, time_offset: f32, snr: f32) -> Self {
+        Transmission {
+            message: message.to_string(),
+            freq_hz,
+            time_offset,
+            snr,
+            drift: None,
+        }
+    }
+
+    pub fn with_drift(mut self, drift: f32) -> Self {
+        self.drift = Some(drift);
+        self
+    }
+}
+
+// 複数局の送信を1スロット分のサンプル列に合成する
+// 信号の振幅はWSJT-Xのft8simと同じく、分散1の白色雑音に対するSNRで決める
+pub struct SlotComposer {
+    protocol: &'static Protocol,
+    sample_rate: u32,
+    pub transmissions: Vec<Transmission>,
+}
+
+impl SlotComposer {
+    pub fn new(protocol: &'static Protocol, sample_rate: u32) -> Self {
+        SlotComposer {
+            protocol,
+            sample_rate,
+            transmissions: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, transmission: Transmission) -> &mut Self {
+        self.transmissions.push(transmission);
+        self
+    }
+
+    // Sum all transmissions into one slot
+    pub fn compose(&self) -> Result<Vec<f32>, Ft8Error> {
+        let protocol = self.protocol;
+        let fs = self.sample_rate as f32;
+        let n_spsym = (0.5 + fs * protocol.symbol_period) as usize;
+        let num_samples = protocol.num_symbols * n_spsym;
+        let mut slot = vec![0.0f32; (protocol.slot_time * fs) as usize];
+        let mut signal = vec![0.0f32; num_samples];
+        let mut tones = vec![0usize; protocol.num_symbols];
+
+        for tx in self.transmissions.iter() {
+            // 局ごとに別のハッシュテーブルを使い、他局のコールサインに影響されないようにする
+            let mut packed = [0u8; FTX_LDPC_K_BYTES];
+            pack77(&tx.message, &CallsignHashTable::new(), &mut packed)?;
+            ftx_encode(protocol, &packed, &mut tones);
+            synth_gfsk_drift(
+                &tones,
+                protocol.num_symbols,
+                tx.freq_hz,
+                tx.drift.unwrap_or(0.0),
+                protocol.symbol_bt,
+                protocol.symbol_period,
+                fs,
+                &mut signal,
+            );
+
+            //SNR(2500Hz帯域)から振幅を求める
+            let amplitude = snr_amplitude(tx.snr, self.sample_rate);
+            //スロットからはみ出す部分は切り捨てる
+            let start = ((self.protocol.tx_start + tx.time_offset) * fs).round() as isize;
+            for (i, s) in signal.iter().enumerate() {
+                let pos = start + i as isize;
+                if pos >= 0 && (pos as usize) < slot.len() {
+                    slot[pos as usize] += amplitude * s;
+                }
+            }
+        }
+        Ok(slot)
+    }
+
+    // Truth file in the format of data/test_NN.txt
+    pub fn truth(&self) -> String {
+        let mut transmissions: Vec<&Transmission> = self.transmissions.iter().collect();
+        transmissions.sort_by(|a, b| a.freq_hz.total_cmp(&b.freq_hz));
+        let mut text = String::new();
+        for tx in transmissions {
+            text.push_str(&format!(
+                "000000 {:3} {:4.1} {:4} {}  {}\n",
+                tx.snr.round() as i32,
+                tx.time_offset,
+                tx.freq_hz.round() as i32,
+                self.protocol.marker,
+                tx.message
+            ));
+        }
+        text
+    }
+
+    // Write the slot as a 32-bit float WAV file and the truth file next to it
+    pub fn write<P: AsRef<Path>>(&self, wav_path: P, truth_path: P) -> Result<(), Ft8Error> {
+        let samples = self.compose()?;
+        let header = wav_io::new_header(self.sample_rate, 32, true, true);
+        let mut wav = File::create(wav_path)?;
+        wav_io::write_to_file(&mut wav, &header, &samples)?;
+        File::create(truth_path)?.write_all(self.truth().as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ft8decode::decode_slot;
+    use crate::monitor::Config;
+    use crate::protocol::FT8;
+
+    #[test]
+    fn crowded_slot_decodes() {
+        // ldpc_bitflipでも復号できる程度のドリフトにとどめる
+        let mut composer = SlotComposer::new(&FT8, 12000);
+        composer
+            .add(Transmission::new("CQ IU8DMZ JN70", 955.0, 0.1, 10.0))
+            .add(Transmission::new("JO1COV PE1OYB JO21", 338.0, 0.3, 0.0))
+            .add(Transmission::new("LY2EW DL1KDA RR73", 824.0, 0.4, 5.0).with_drift(0.2))
+            .add(Transmission::new("EA9ACD HA5LGO -13", 1292.0, -0.2, 0.0));
+        assert_eq!(
+            composer.truth().lines().next(),
+            Some("000000   0  0.3  338 ~  JO1COV PE1OYB JO21")
+        );
+
+        let config = Config::default_for(&FT8);
+        let samples = composer.compose().unwrap();
+        let mut decoded: Vec<String> = decode_slot(&samples, &config)
+            .into_iter()
+            .map(|message| message.text)
+            .collect();
+        decoded.sort();
+        let mut expected: Vec<&str> = composer
+            .transmissions
+            .iter()
+            .map(|tx| tx.message.as_str())
+            .collect();
+        expected.sort();
+        assert_eq!(decoded, expected);
+
+        let wav_path = std::env::temp_dir().join("rustft8_composer_test.wav");
+        let truth_path = std::env::temp_dir().join("rustft8_composer_test.txt");
+        composer.write(&wav_path, &truth_path).unwrap();
+        let (header, written) = wav_io::read_from_file(File::open(&wav_path).unwrap()).unwrap();
+        assert_eq!(header.sample_rate, 12000);
+        assert_eq!(written.len(), samples.len());
+        assert_eq!(
+            std::fs::read_to_string(&truth_path).unwrap(),
+            composer.truth()
+        );
+        std::fs::remove_file(&wav_path).unwrap();
+        std::fs::remove_file(&truth_path).unwrap();
+    }
+}
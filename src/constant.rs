@@ -13,6 +13,27 @@ pub const FT8_NUM_SYNC: usize = 3;
 pub const FT8_SYNC_OFFSET: usize = 36;
 //　Costas array
 pub const FT8_COSTAS_PATTERN: [usize; 7] = [3, 1, 4, 0, 6, 5, 2];
+// FT4 symbol Period and Slot time
+pub const FT4_SYMBOL_PERIOD: f32 = 0.048;
+pub const FT4_SLOT_TIME: f32 = 7.5;
+// Data symbols
+pub const FT4_ND: usize = 87;
+// Total channel symbols (2 ramp + 16 sync + FT4_ND)
+pub const FT4_NN: usize = 105;
+// Length of each sync group
+pub const FT4_LENGTH_SYNC: usize = 4;
+// Number of sync groups
+pub const FT4_NUM_SYNC: usize = 4;
+// Offset between sync groups
+pub const FT4_SYNC_OFFSET: usize = 33;
+// Costas arrays, one per sync group
+pub const FT4_COSTAS_PATTERN: [[usize; 4]; 4] = [[0, 1, 3, 2], [1, 0, 2, 3], [2, 3, 1, 0], [3, 2, 0, 1]];
+// Gray code map (FT4 bits -> channel symbols)
+pub const FT4_GRAY_MAP: [usize; 4] = [0, 1, 3, 2];
+// Scrambling sequence XORed with the 77-bit FT4 payload
+pub const FT4_XOR_SEQUENCE: [u8; 10] = [0x4A, 0x5E, 0x89, 0xB4, 0xB0, 0x8A, 0x79, 0x55, 0xBE, 0x28];
+// Reference bandwidth of the reported and simulated SNR (Hz), same as WSJT-X
+pub const SNR_BANDWIDTH: f32 = 2500.0;
 // Number of bits in the encoded message (payload with LDPC checksum bits)
 pub const FTX_LDPC_N: usize = 174;
 // Number of payload bits (including CRC)
@@ -20,15 +41,39 @@ pub const FTX_LDPC_K: usize = 91;
 // Number of LDPC checksum bits (FTX_LDPC_N - FTX_LDPC_K)
 pub const FTX_LDPC_M: usize = 83;
 // Number of whole bytes needed to store 174 bits (full message)
-pub const FTX_LDPC_N_BYTES: usize = (FTX_LDPC_N + 7) / 8;
+pub const FTX_LDPC_N_BYTES: usize = FTX_LDPC_N.div_ceil(8);
 // Number of whole bytes needed to store 91 bits (payload + CRC only)
-pub const FTX_LDPC_K_BYTES: usize = (FTX_LDPC_K + 7) / 8;
+pub const FTX_LDPC_K_BYTES: usize = FTX_LDPC_K.div_ceil(8);
 // Gray code map (FTx bits -> channel symbols)
 pub const FT8_GRAY_MAP: [usize; 8] = [0, 1, 3, 2, 5, 6, 4, 7];
 // Define CRC parameters
 // CRC-14 polynomial without the leading (MSB) 1
 pub const FT8_CRC_POLYNOMIAL: u16 = 0x2757;
 pub const FT8_CRC_WIDTH: usize = 14;
+// Number of special tokens (DE, QRZ, CQ, CQ_nnn, CQ_aaaa) in a 28-bit callsign field
+pub const NTOKENS: u32 = 2063592;
+// Number of 22-bit hash values
+pub const MAX22: u32 = 4194304;
+// Number of 4-character grid locators
+pub const MAXGRID4: u16 = 32400;
+
+// ARRL/RAC sections for ARRL Field Day messages (i3=0 n3=3,4)
+pub const ARRL_SECTIONS: [&str; 86] = [
+    "AB", "AK", "AL", "AR", "AZ", "BC", "CO", "CT", "DE", "EB", "EMA", "ENY", "EPA", "EWA", "GA",
+    "GTA", "IA", "ID", "IL", "IN", "KS", "KY", "LA", "LAX", "MAR", "MB", "MDC", "ME", "MI", "MN",
+    "MO", "MS", "MT", "NC", "ND", "NE", "NFL", "NH", "NL", "NLI", "NM", "NNJ", "NNY", "NT", "NTX",
+    "NV", "OH", "OK", "ONE", "ONN", "ONS", "OR", "ORG", "PAC", "PR", "QC", "RI", "SB", "SC", "SCV",
+    "SD", "SDG", "SF", "SFL", "SJV", "SK", "SNJ", "STX", "SV", "TN", "UT", "VA", "VI", "VT", "WCF",
+    "WI", "WMA", "WNY", "WPA", "WTX", "WV", "WWA", "WY", "DX", "PE", "NB",
+];
+// US states and Canadian provinces for ARRL RTTY Roundup messages (i3=3)
+pub const RTTY_MULTIPLIERS: [&str; 65] = [
+    "AL", "AK", "AZ", "AR", "CA", "CO", "CT", "DE", "FL", "GA", "HI", "ID", "IL", "IN", "IA", "KS",
+    "KY", "LA", "ME", "MD", "MA", "MI", "MN", "MS", "MO", "MT", "NE", "NV", "NH", "NJ", "NM", "NY",
+    "NC", "ND", "OH", "OK", "OR", "PA", "RI", "SC", "SD", "TN", "TX", "UT", "VT", "VA", "WA", "WV",
+    "WI", "WY", "NB", "NS", "QC", "ON", "MB", "SK", "AB", "BC", "NWT", "NF", "LB", "NU", "YT",
+    "PEI", "DC",
+];
 
 // Parity generator matrix for (174,91) LDPC code, stored in bitpacked format (MSB first)
 pub const FTX_LDPC_GENERATOR: [[u8; FTX_LDPC_K_BYTES]; FTX_LDPC_M] = [
@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// Reasons why a message could not be packed, unpacked or decoded
+#[derive(Debug)]
+pub enum Ft8Error {
+    // Token is not a callsign that fits the field
+    InvalidCallsign(String),
+    // Grid locator out of range
+    GridOutOfRange(String),
+    // Signal report or RST out of range
+    ReportOutOfRange(String),
+    // Contest exchange (class, section, state or serial number) not recognised
+    InvalidExchange(String),
+    // Free text too long or with a character outside the alphabet
+    InvalidText(String),
+    // Message does not have the shape of the message type
+    InvalidMessage(String),
+    // i3/n3 combination that has no decoder
+    UnsupportedMessageType { i3: u8, n3: u8 },
+    // Packed field holds a value that no encoder produces
+    InvalidField { field: &'static str, value: u64 },
+    // CRC of the decoded codeword does not match
+    CrcMismatch { extracted: u16, calculated: u16 },
+    // LDPC decoder stopped with unsatisfied parity checks
+    LdpcFailure { errors: usize },
+    // FFT window whose coefficients do not have a positive sum
+    InvalidWindow { sum: f32 },
+    // LDPC degree distribution whose fractions do not add up to 1
+    InvalidDistribution { sum: f32 },
+    // LDPC column degree of zero or above the number of checks
+    InvalidDegree { degree: usize, checks: usize },
+    // WAV file could not be read or written
+    WavIo(Box<dyn Error + Send + Sync>),
+    Io(io::Error),
+}
+
+impl fmt::Display for Ft8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ft8Error::InvalidCallsign(call) => write!(f, "invalid callsign '{}'", call),
+            Ft8Error::GridOutOfRange(grid) => write!(f, "grid locator '{}' out of range", grid),
+            Ft8Error::ReportOutOfRange(report) => write!(f, "report '{}' out of range", report),
+            Ft8Error::InvalidExchange(exchange) => write!(f, "invalid exchange '{}'", exchange),
+            Ft8Error::InvalidText(text) => write!(f, "cannot send '{}' as free text", text),
+            Ft8Error::InvalidMessage(msg) => write!(f, "cannot parse message '{}'", msg),
+            Ft8Error::UnsupportedMessageType { i3, n3 } => {
+                write!(f, "unsupported message type i3={} n3={}", i3, n3)
+            }
+            Ft8Error::InvalidField { field, value } => {
+                write!(f, "invalid value {} in field {}", value, field)
+            }
+            Ft8Error::CrcMismatch {
+                extracted,
+                calculated,
+            } => write!(
+                f,
+                "CRC mismatch: received {:04x}, calculated {:04x}",
+                extracted, calculated
+            ),
+            Ft8Error::LdpcFailure { errors } => {
+                write!(f, "LDPC decoding failed with {} parity errors", errors)
+            }
+            Ft8Error::InvalidWindow { sum } => {
+                write!(f, "window coefficients sum to {}, need a positive sum", sum)
+            }
+            Ft8Error::InvalidDistribution { sum } => {
+                write!(f, "degree distribution sums to {} instead of 1", sum)
+            }
+            Ft8Error::InvalidDegree { degree, checks } => {
+                write!(f, "column degree {} does not fit {} checks", degree, checks)
+            }
+            Ft8Error::WavIo(e) => write!(f, "WAV I/O error: {}", e),
+            Ft8Error::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl Error for Ft8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Ft8Error::WavIo(e) => Some(e.as_ref()),
+            Ft8Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Ft8Error {
+    fn from(e: io::Error) -> Self {
+        Ft8Error::Io(e)
+    }
+}
+
+impl From<wav_io::reader::DecodeError> for Ft8Error {
+    fn from(e: wav_io::reader::DecodeError) -> Self {
+        Ft8Error::WavIo(Box::new(e))
+    }
+}
+
+impl From<wav_io::writer::EncoderError> for Ft8Error {
+    fn from(e: wav_io::writer::EncoderError) -> Self {
+        Ft8Error::WavIo(Box::new(e))
+    }
+}
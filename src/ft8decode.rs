@@ -1,29 +1,48 @@
 use crate::constant::*;
 use crate::crc::{ftx_compute_crc, ftx_extract_crc};
+use crate::error::Ft8Error;
+use crate::hashtable::CallsignHashTable;
+use crate::ft8encode::ftx_encode;
 use crate::ldpc::*;
-use crate::monitor::{Candidate, Waterfall};
-use crate::unpack::*;
+use crate::monitor::{Candidate, MagValue, Magnitudes, Waterfall};
+use crate::payload::Ft8Payload;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashSet;
+
+// Percentile of the neighbouring bins taken as the noise floor
+const NOISE_PERCENTILE: f32 = 0.25;
+// WSJT-X does not report SNRs below -24 dB
+const MIN_SNR: f32 = -24.0;
 
 pub struct FT8FindSync<'a> {
     wf: &'a Waterfall,
 }
 
 impl<'a> FT8FindSync<'a> {
-    pub fn new(wf: &Waterfall) -> FT8FindSync {
+    pub fn new(wf: &Waterfall) -> FT8FindSync<'_> {
         FT8FindSync { wf }
     }
     //対象候補の信号とコスタス配列との相関によりスコアを求める
-    fn ft8_sync_score(&self, candidate: &Candidate) -> i32 {
-        let mut score = 0i32;
+    fn sync_score(&self, candidate: &Candidate) -> i32 {
+        match &self.wf.mag {
+            Magnitudes::U8(mag) => self.sync_score_in(mag, candidate),
+            Magnitudes::F32(mag) => self.sync_score_in(mag, candidate),
+        }
+    }
+
+    fn sync_score_in<T: MagValue>(&self, mag: &[T], candidate: &Candidate) -> i32 {
+        let get = |i: usize| mag[i].to_f32();
+        let mut score = 0.0f32;
         let mut num_average = 0i32;
         let wf = self.wf;
+        let max_tone = wf.protocol.num_tones - 1;
 
-        //FT8に3箇所あるコスタス配列を探す
-        for m in 0..FT8_NUM_SYNC {
+        //FT8は3箇所、FT4は4箇所あるコスタス配列を探す
+        for (start, pattern) in wf.protocol.sync {
             //コスタス配列の各要素についてループ
-            for k in 0..FT8_LENGTH_SYNC {
-                //コスタス配列の開始位置は0,36,72ビット目
-                let block = (FT8_SYNC_OFFSET * m) + k;
+            for (k, &sm) in pattern.iter().enumerate() {
+                //コスタス配列の開始位置はFT8が0,36,72、FT4が1,34,67,100シンボル目
+                let block = start + k;
                 let block_abs = candidate.time_offset + block as i32;
                 //スペクトログラム内にあることをチェック
                 if block_abs < 0 {
@@ -35,33 +54,32 @@ impl<'a> FT8FindSync<'a> {
                 //対象候補のスペクトログラム中の位置を求め
                 //コスタス配列との相関をスコア化する
                 let p8 = ((block * wf.block_stride) as i32 + wf.get_index(candidate)) as usize;
-                let sm = FT8_COSTAS_PATTERN[k];
                 //スコアはコスタス配列位置の信号強度とそれ以外の位置のスコアの差分
                 //1.コスタス配列内では上下のトーンとの差分をスコアに加算
                 if sm > 0 {
-                    score += wf.mag[p8 + sm] as i32 - wf.mag[p8 + sm - 1] as i32;
+                    score += get(p8 + sm) - get(p8 + sm - 1);
                     num_average += 1;
                 }
-                if sm < 7 {
-                    score += wf.mag[p8 + sm] as i32 - wf.mag[p8 + sm + 1] as i32;
+                if sm < max_tone {
+                    score += get(p8 + sm) - get(p8 + sm + 1);
                     num_average += 1;
                 }
                 //2.前後のシンボルとの差分をスコアに加算
                 if (k > 0) && (block_abs > 0) {
-                    score += wf.mag[p8 + sm] as i32 - wf.mag[p8 + sm - wf.block_stride] as i32;
+                    score += get(p8 + sm) - get(p8 + sm - wf.block_stride);
                     num_average += 1;
                 }
-                if ((k + 1) < FT8_LENGTH_SYNC) && ((block_abs + 1) < wf.num_blocks as i32) {
-                    score += wf.mag[p8 + sm] as i32 - wf.mag[p8 + sm + wf.block_stride] as i32;
+                if ((k + 1) < pattern.len()) && ((block_abs + 1) < wf.num_blocks as i32) {
+                    score += get(p8 + sm) - get(p8 + sm + wf.block_stride);
                     num_average += 1;
                 }
             }
         }
         //スコアを平均化
         if num_average > 0 {
-            score /= num_average;
+            score /= num_average as f32;
         }
-        score
+        score as i32
     }
 
     pub fn ft8_find_sync(
@@ -76,7 +94,7 @@ impl<'a> FT8FindSync<'a> {
         for time_sub in 0..self.wf.time_osr {
             for freq_sub in 0..self.wf.freq_osr {
                 //2. 1.で指定された時間の前後
-                for time_offset in -12..24 {
+                for time_offset in self.wf.protocol.time_offset_range.clone() {
                     //3. STFTで解析した範囲の周波数の範囲(=ビン数)
                     for freq_offset in freq_from..freq_to {
                         let mut c = Candidate {
@@ -87,7 +105,7 @@ impl<'a> FT8FindSync<'a> {
                             freq_sub,
                         };
                         //指定された範囲のスコアを求める
-                        let score = self.ft8_sync_score(&c);
+                        let score = self.sync_score(&c);
                         //スコアが所定値以下なら繰り返し
                         if score < min_score {
                             continue;
@@ -100,7 +118,7 @@ impl<'a> FT8FindSync<'a> {
             }
         }
         //スコアの高い順にソート
-        candidates.sort_by(|a, b| b.score.cmp(&a.score));
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.score));
         candidates.len()
     }
 }
@@ -109,7 +127,15 @@ impl<'a> FT8FindSync<'a> {
 pub struct Message {
     pub df: Vec<(i32, f32, f32)>,
     pub text: String,
+    pub payload: Option<Ft8Payload>,
     pub hash: u16,
+    pub snr: f32, // SNR in 2500 Hz (dB), as reported by WSJT-X
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Message {
@@ -117,30 +143,23 @@ impl Message {
         Message {
             df: Vec::new(),
             text: String::new(),
+            payload: None,
             hash: 0,
+            snr: MIN_SNR,
         }
     }
 }
 
 pub struct FT8Decode<'a> {
     wf: &'a Waterfall,
+    hash_table: &'a CallsignHashTable,
     pub message: Vec<Message>,
-}
-
-fn max2(a: f32, b: f32) -> f32 {
-    if a >= b {
-        a
-    } else {
-        b
-    }
-}
-
-fn max4(a: f32, b: f32, c: f32, d: f32) -> f32 {
-    max2(max2(a, b), max2(c, d))
+    noise_floor: OnceCell<Vec<f32>>, // Computed at the first decode
+    ldpc_scratch: RefCell<LdpcScratch>, // Reused by every candidate
 }
 
 fn pack_bits(bit_array: &[u8; FTX_LDPC_N], num_bits: usize, packed: &mut [u8; FTX_LDPC_K_BYTES]) {
-    let num_bytes = (num_bits + 7) / 8;
+    let num_bytes = num_bits.div_ceil(8);
     for pkd in packed.iter_mut().take(num_bytes) {
         *pkd = 0;
     }
@@ -161,10 +180,13 @@ fn pack_bits(bit_array: &[u8; FTX_LDPC_N], num_bits: usize, packed: &mut [u8; FT
 }
 
 impl<'a> FT8Decode<'a> {
-    pub fn new(wf: &'a Waterfall) -> FT8Decode {
+    pub fn new(wf: &'a Waterfall, hash_table: &'a CallsignHashTable) -> FT8Decode<'a> {
         FT8Decode {
             wf,
+            hash_table,
             message: Vec::new(),
+            noise_floor: OnceCell::new(),
+            ldpc_scratch: RefCell::new(LdpcScratch::default()),
         }
     }
 
@@ -189,58 +211,139 @@ impl<'a> FT8Decode<'a> {
         }
     }
 
-    fn ft8_extract_symbol(&self, idx: usize, logl: &mut [f32; FTX_LDPC_N], bit_idx: usize) {
+    fn ftx_extract_symbol<T: MagValue>(
+        &self,
+        mag: &[T],
+        idx: usize,
+        logl: &mut [f32; FTX_LDPC_N],
+        bit_idx: usize,
+    ) {
+        let protocol = self.wf.protocol;
+        let num_bits = protocol.bits_per_symbol();
         let mut s2: [f32; 8] = [0.0; 8];
-        //3bitグレイコードに対応するトーンの強度をs2に入れる
-        for j in 0..8 {
-            s2[j] = self.wf.mag[idx + FT8_GRAY_MAP[j]] as f32;
+        //グレイコードに対応するトーンの強度をs2に入れる
+        for (j, tone) in protocol.gray_map.iter().enumerate() {
+            s2[j] = mag[idx + tone].to_f32();
         }
         //各bit毎の対数尤度比LLR(Log Likelihood Ratio)をビット毎に求める　LLR = log(P(b=1)/P(b=0))
-        //グレイコード上のMSBのLLRはtone4-7(1)の最大値からtone0-3(0)の最大値を引いたもの
-        logl[bit_idx + 0] = max4(s2[4], s2[5], s2[6], s2[7]) - max4(s2[0], s2[1], s2[2], s2[3]);
-        //同様に2bit目はtone2,3,6,7(1)の最大値からtone0,1,5,4(0)の最大値を引いたもの
-        logl[bit_idx + 1] = max4(s2[2], s2[3], s2[6], s2[7]) - max4(s2[0], s2[1], s2[5], s2[4]);
-        //同様に3bitも計算
-        logl[bit_idx + 2] = max4(s2[1], s2[3], s2[5], s2[7]) - max4(s2[0], s2[2], s2[6], s2[4]);
+        //FT8のMSBのLLRはtone4-7(1)の最大値からtone0-3(0)の最大値を引いたもの
+        //同様に2bit目はtone2,3,6,7(1)とtone0,1,4,5(0)、3bit目は奇数と偶数のトーン
+        for i in 0..num_bits {
+            let shift = num_bits - 1 - i;
+            let mut max1 = f32::MIN;
+            let mut max0 = f32::MIN;
+            for (j, s) in s2.iter().enumerate().take(protocol.num_tones) {
+                if (j >> shift) & 1 != 0 {
+                    max1 = max1.max(*s);
+                } else {
+                    max0 = max0.max(*s);
+                }
+            }
+            logl[bit_idx + i] = max1 - max0;
+        }
     }
 
-    fn ft8_extract_likelihood(&self, c: &Candidate, log174: &mut [f32; FTX_LDPC_N]) {
-        //58bit分のシンボルを取り出す
-        for k in 0..FT8_ND {
-            //コスタス配列を飛ばしたシンボル部分
-            let sym_idx = k + if k < 29 { 7 } else { 14 };
-            //3bit分ずつ取り出す
-            let bit_idx = 3 * k;
+    fn ftx_extract_likelihood(&self, c: &Candidate, log174: &mut [f32; FTX_LDPC_N]) {
+        match &self.wf.mag {
+            Magnitudes::U8(mag) => self.extract_likelihood_in(mag, c, log174),
+            Magnitudes::F32(mag) => self.extract_likelihood_in(mag, c, log174),
+        }
+    }
+
+    fn extract_likelihood_in<T: MagValue>(
+        &self,
+        mag: &[T],
+        c: &Candidate,
+        log174: &mut [f32; FTX_LDPC_N],
+    ) {
+        let num_bits = self.wf.protocol.bits_per_symbol();
+        //FT8は58シンボル分、FT4は87シンボル分を取り出す
+        //コスタス配列とランプを飛ばしたシンボル部分
+        for (k, sym_idx) in self.wf.protocol.data_symbols().enumerate() {
+            //FT8は3bit分ずつ、FT4は2bit分ずつ取り出す
+            let bit_idx = num_bits * k;
 
             //スペクトログラム上でシンボルがあるブロックを取り出す
             let block = c.time_offset + sym_idx as i32;
             //スペクトログラム外なら0
             if (block < 0) || (block >= self.wf.num_blocks as i32) {
-                log174[bit_idx + 0] = 0.0f32;
-                log174[bit_idx + 1] = 0.0f32;
-                log174[bit_idx + 2] = 0.0f32;
+                for l in log174[bit_idx..bit_idx + num_bits].iter_mut() {
+                    *l = 0.0f32;
+                }
             } else {
                 //スペクトログラム内であればシンボルを対数尤度で取り出す
                 let idx = (self.wf.get_index(c) + (sym_idx * self.wf.block_stride) as i32) as usize;
-                self.ft8_extract_symbol(idx, log174, bit_idx);
+                self.ftx_extract_symbol(mag, idx, log174, bit_idx);
             }
         }
     }
 
-    pub fn ft8_decode(&self, c: &Candidate, max_iteration: i32, message: &mut Message) -> bool {
+    // デコードしたメッセージのトーンの電力とノイズフロアからSNR(2500Hz帯域)を求める
+    fn ftx_snr(&self, c: &Candidate, payload: &[u8; FTX_LDPC_K_BYTES]) -> f32 {
+        let wf = self.wf;
+        let protocol = wf.protocol;
+        //メッセージを再度符号化して各シンボルのトーンを求める
+        let mut tones = vec![0usize; protocol.num_symbols];
+        ftx_encode(protocol, payload, &mut tones);
+
+        //トーンのビンのノイズフロア
+        let floor = self
+            .noise_floor
+            .get_or_init(|| wf.noise_floor(NOISE_PERCENTILE));
+        let row = c.freq_sub * wf.num_bins + c.freq_offset;
+        let noise =
+            floor[row..row + protocol.num_tones].iter().sum::<f32>() / protocol.num_tones as f32;
+        //0.5dB単位の値を電力に戻す
+        let power = |mag: f32| 10f32.powf(mag / 20.0);
+
+        let mut signal = 0.0f32;
+        let mut num_symbols = 0;
+        for (sym_idx, &tone) in tones.iter().enumerate() {
+            let block = c.time_offset + sym_idx as i32;
+            if protocol.is_ramp(sym_idx) || block < 0 || block >= wf.num_blocks as i32 {
+                continue;
+            }
+            let idx = (wf.get_index(c) + (sym_idx * wf.block_stride) as i32) as usize;
+            signal += power(wf.mag.get(idx + tone));
+            num_symbols += 1;
+        }
+        if num_symbols == 0 {
+            return MIN_SNR;
+        }
+        //トーンのビンの雑音を引き、窓の中央にしか入らない分を補正する
+        let ratio = (signal / num_symbols as f32 - noise) / noise / wf.symbol_gain;
+        if ratio.is_nan() || ratio <= 0.0 {
+            return MIN_SNR;
+        }
+        (10.0 * (ratio * wf.noise_bandwidth / SNR_BANDWIDTH).log10()).max(MIN_SNR)
+    }
+
+    pub fn ft8_decode(
+        &self,
+        c: &Candidate,
+        max_iteration: i32,
+        message: &mut Message,
+    ) -> Result<(), Ft8Error> {
         let mut log174: [f32; FTX_LDPC_N] = [0.0f32; FTX_LDPC_N];
 
         //デコード候補のある位置のスペクトログラムからシンボルを取り出す
-        self.ft8_extract_likelihood(c, &mut log174);
+        self.ftx_extract_likelihood(c, &mut log174);
         //各ビットのLLRを正規化
         self.ftx_normalize_logl(&mut log174);
 
         let mut plain174 = [0u8; FTX_LDPC_N];
         // LDPCデコードを実行
-        let ldpc_errors = ldpc_decode(log174, max_iteration, &mut plain174);
+        let ldpc_errors = ldpc_decode(
+            log174,
+            max_iteration,
+            &mut plain174,
+            &mut self.ldpc_scratch.borrow_mut(),
+        );
 
         if ldpc_errors > 0 {
-            return false;
+            return Err(Ft8Error::LdpcFailure {
+                errors: ldpc_errors,
+            });
         }
 
         let mut a91 = [0u8; FTX_LDPC_K_BYTES];
@@ -257,23 +360,282 @@ impl<'a> FT8Decode<'a> {
 
         //受信時のCRCと受信メッセージから生成したCRCが異なればデコード失敗
         if crc_extracted != crc_calculated {
-            return false;
+            return Err(Ft8Error::CrcMismatch {
+                extracted: crc_extracted,
+                calculated: crc_calculated,
+            });
         }
 
-        //パックされたビット列からメッセージを展開
-        if unpack77(&a91, &mut message.text) < 0 {
-            return false;
+        //FT4はスクランブルを元に戻す
+        for (a, x) in a91.iter_mut().zip(self.wf.protocol.payload_xor) {
+            *a ^= x;
         }
+
+        //パックされたビット列からメッセージを展開
+        let payload = Ft8Payload::from_bytes(&a91, self.hash_table)?;
+        message.text = payload.to_string();
+        message.payload = Some(payload);
+        message.snr = self.ftx_snr(c, &a91);
         
         //メッセージのDF/DTを求め
+        let symbol_period = self.wf.protocol.symbol_period;
         let freq_hz = (c.freq_offset as f32 + c.freq_sub as f32 / self.wf.freq_osr as f32)
-            / FT8_SYMBOL_PERIOD;
+            / symbol_period;
         let time_sec = (c.time_offset as f32 + c.time_sub as f32 / self.wf.time_osr as f32)
-            * FT8_SYMBOL_PERIOD;
+            * symbol_period;
 
         //メッセージのCRCをキーにデコードされたメッセージをハッシュに登録
         message.hash = crc_calculated;
         message.df.push((c.score, time_sec, freq_hz));
-        true
+        Ok(())
+    }
+}
+
+// スロットの途中で何度かデコードする(早期デコード)
+// 各パスはその時点で全データシンボルが揃った候補だけをデコードし、
+// 前のパスで報告したメッセージは報告しない
+pub struct IncrementalDecoder {
+    passes: Vec<f32>, // Decode times from the slot start (s)
+    next_pass: usize,
+    reported: HashSet<u16>, // CRCs of the messages reported in this slot
+}
+
+impl IncrementalDecoder {
+    pub fn new(passes: &[f32]) -> Self {
+        let mut passes = passes.to_vec();
+        passes.sort_by(f32::total_cmp);
+        IncrementalDecoder {
+            passes,
+            next_pass: 0,
+            reported: HashSet::new(),
+        }
+    }
+
+    pub fn passes(&self) -> &[f32] {
+        &self.passes
+    }
+
+    // Call at the start of every slot
+    pub fn reset(&mut self) {
+        self.next_pass = 0;
+        self.reported.clear();
+    }
+
+    // Run a pass if one is due for the waterfall so far and return the new messages.
+    // All remaining passes are due once the waterfall is full.
+    pub fn poll(
+        &mut self,
+        wf: &Waterfall,
+        min_score: i32,
+        max_iteration: i32,
+        hash_table: &CallsignHashTable,
+    ) -> Vec<Message> {
+        let elapsed = wf.num_blocks as f32 * wf.protocol.symbol_period;
+        let full = wf.num_blocks >= wf.max_blocks;
+        let mut due = false;
+        while self.next_pass < self.passes.len() && (full || elapsed >= self.passes[self.next_pass])
+        {
+            self.next_pass += 1;
+            due = true;
+        }
+        if due {
+            self.decode_pass(wf, full, min_score, max_iteration, hash_table)
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Run the remaining passes at the end of the slot, when the waterfall may
+    // be a few blocks short of max_blocks
+    pub fn finish(
+        &mut self,
+        wf: &Waterfall,
+        min_score: i32,
+        max_iteration: i32,
+        hash_table: &CallsignHashTable,
+    ) -> Vec<Message> {
+        if self.next_pass < self.passes.len() {
+            self.next_pass = self.passes.len();
+            self.decode_pass(wf, true, min_score, max_iteration, hash_table)
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Decode the candidates whose symbols are all in the waterfall.
+    // In the last pass no more blocks will come, so candidates running past
+    // the end of the slot are decoded too (as ft8_decode does for a whole slot).
+    fn decode_pass(
+        &mut self,
+        wf: &Waterfall,
+        last_pass: bool,
+        min_score: i32,
+        max_iteration: i32,
+        hash_table: &CallsignHashTable,
+    ) -> Vec<Message> {
+        let last_symbol = wf.protocol.data_symbols().last().unwrap_or(0) as i32;
+        let mut candidates = Vec::new();
+        FT8FindSync::new(wf).ft8_find_sync(
+            0,
+            wf.num_bins - (wf.protocol.num_tones - 1),
+            min_score,
+            &mut candidates,
+        );
+        let decode = FT8Decode::new(wf, hash_table);
+        let mut messages: Vec<Message> = Vec::new();
+        for c in candidates.iter() {
+            //まだ受信していないシンボルがある候補は次のパスに回す
+            if !last_pass && c.time_offset + last_symbol >= wf.num_blocks as i32 {
+                continue;
+            }
+            let mut message = Message::new();
+            if decode.ft8_decode(c, max_iteration, &mut message).is_err() {
+                continue;
+            }
+            if self.reported.insert(message.hash) {
+                messages.push(message);
+            } else if let Some(m) = messages.iter_mut().find(|m| m.hash == message.hash) {
+                m.df.append(&mut message.df);
+            }
+        }
+        messages
+    }
+}
+
+// 1スロット分のサンプルを同期探索からLDPC復号まで通し、復号できたメッセージを
+// スコアの高い順に重複なく返す
+#[cfg(test)]
+pub(crate) fn decode_slot(samples: &[f32], config: &crate::monitor::Config) -> Vec<Message> {
+    let mut mon = crate::monitor::Monitor::new(config, samples);
+    mon.process_all();
+    let mut candidates = Vec::new();
+    FT8FindSync::new(&mon.wf).ft8_find_sync(
+        0,
+        mon.wf.num_bins - (config.protocol.num_tones - 1),
+        config.sync_min_score,
+        &mut candidates,
+    );
+    let hash_table = CallsignHashTable::new();
+    let decode = FT8Decode::new(&mon.wf, &hash_table);
+    let mut decoded: Vec<Message> = Vec::new();
+    for c in &candidates {
+        let mut message = Message::new();
+        if decode.ft8_decode(c, config.ldpc_max_iteration, &mut message).is_ok()
+            && decoded.iter().all(|m| m.text != message.text)
+        {
+            decoded.push(message);
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ft8encode::ft4_encode;
+    use crate::monitor::{Config, Monitor};
+    use crate::pack::pack77;
+    use crate::protocol::{FT4, FT8};
+
+    #[test]
+    fn ft4_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        let mut packed = [0u8; FTX_LDPC_K_BYTES];
+        pack77("CQ K1ABC FN42", &hash_table, &mut packed).unwrap();
+        let mut tones = [0usize; FT4_NN];
+        ft4_encode(&packed, &mut tones);
+        assert_eq!(tones[1..5], FT4_COSTAS_PATTERN[0]);
+        assert_eq!(tones[100..104], FT4_COSTAS_PATTERN[3]);
+
+        // Phase-continuous 4-FSK at 1000 Hz, starting 0.5 s into the slot
+        let config = Config::default_for(&FT4);
+        let n_spsym = (config.sample_rate as f32 * FT4_SYMBOL_PERIOD) as usize;
+        let mut samples = vec![0.0f32; (FT4_SLOT_TIME * config.sample_rate as f32) as usize];
+        let mut phi = 0.0f32;
+        for (i, tone) in tones.iter().enumerate() {
+            let f = 1000.0 + *tone as f32 / FT4_SYMBOL_PERIOD;
+            for j in 0..n_spsym {
+                samples[6000 + i * n_spsym + j] = phi.sin();
+                phi += 2.0 * std::f32::consts::PI * f / config.sample_rate as f32;
+            }
+        }
+
+        let decoded = decode_slot(&samples, &config).into_iter().next();
+        let message = decoded.expect("no FT4 message decoded");
+        assert_eq!(message.text, "CQ K1ABC FN42");
+        let (_, time_sec, freq_hz) = message.df[0];
+        assert!((freq_hz - 1000.0).abs() < 10.0);
+        assert!((time_sec - 0.5).abs() < 0.1);
+    }
+
+    #[test]
+    fn early_passes_report_once() {
+        use crate::composer::{SlotComposer, Transmission};
+        use crate::monitor::StreamMonitor;
+
+        // The first station ends its data at 11.6 s, the second at 12.5 s
+        let mut composer = SlotComposer::new(&FT8, 12000);
+        composer
+            .add(Transmission::new("CQ JA1ZLO PM95", 1000.0, -0.4, 0.0))
+            .add(Transmission::new("JA1ZLO JH1ABC -10", 1500.0, 0.5, 0.0));
+        let samples = composer.compose().unwrap();
+        let config = Config::default_for(&FT8);
+
+        let hash_table = CallsignHashTable::new();
+        let mut mon = StreamMonitor::new(&config);
+        let mut decoder = IncrementalDecoder::new(FT8.decode_passes);
+        let mut decoded = Vec::new();
+        for chunk in samples.chunks(480) {
+            if mon.push(chunk) == 0 {
+                continue;
+            }
+            let messages = decoder.poll(&mon.wf, 10, 20, &hash_table);
+            let time = mon.wf.num_blocks as f32 * FT8.symbol_period;
+            decoded.extend(messages.into_iter().map(|m| (time, m.text)));
+        }
+        let time = mon.wf.num_blocks as f32 * FT8.symbol_period;
+        for m in decoder.finish(&mon.wf, 10, 20, &hash_table) {
+            decoded.push((time, m.text));
+        }
+
+        assert_eq!(decoded.len(), 2, "{:?}", decoded);
+        assert_eq!(decoded[0].1, "CQ JA1ZLO PM95");
+        assert!(decoded[0].0 < 12.0);
+        assert_eq!(decoded[1].1, "JA1ZLO JH1ABC -10");
+        assert!(decoded[1].0 >= 13.5 && decoded[1].0 < 14.0);
+    }
+
+    #[test]
+    fn snr_matches_channel() {
+        use crate::channel::Channel;
+        use crate::composer::{SlotComposer, Transmission};
+
+        let mut composer = SlotComposer::new(&FT8, 12000);
+        composer
+            .add(Transmission::new("CQ JA1ZLO PM95", 1200.0, 0.2, -12.0))
+            .add(Transmission::new("JA1ZLO JH1ABC -10", 600.0, 0.1, 5.0));
+        let samples = Channel::awgn(12000).apply(&composer.compose().unwrap());
+        let config = Config {
+            time_osr: 4,
+            ..Config::default_for(&FT8)
+        };
+        let mut mon = Monitor::new(&config, &samples);
+        mon.process_all();
+        // White noise has a flat floor away from the stations
+        let floor = mon.wf.noise_floor(0.25);
+        assert_eq!(floor.len(), mon.wf.freq_osr * mon.wf.num_bins);
+        assert!((floor[300] / floor[800] - 1.0).abs() < 0.1);
+        // The floor under a strong station is taken from the gaps next to it
+        let average = mon.wf.average_spectrum();
+        assert!(average[100] > 10.0 * floor[100]);
+        assert!((floor[100] / floor[800] - 1.0).abs() < 0.1);
+
+        let hash_table = CallsignHashTable::new();
+        let messages = IncrementalDecoder::new(&[15.0]).finish(&mon.wf, 10, 20, &hash_table);
+        assert_eq!(messages.len(), 2);
+        for message in messages {
+            let expected = if message.text == "CQ JA1ZLO PM95" { -12.0 } else { 5.0 };
+            assert!((message.snr - expected).abs() < 1.5, "{} {}", message.text, message.snr);
+        }
     }
 }
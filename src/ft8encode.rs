@@ -1,133 +1,102 @@
 use crate::constant::*;
 use crate::crc::*;
-
-// Returns 1 if an odd number of bits are set in x, zero otherwise
-pub fn parity8(mut x: u8) -> u8 {
-    x ^= x >> 4; // a b c d ae bf cg dh
-    x ^= x >> 2; // a b ac bd cae dbf aecg bfdh
-    x ^= x >> 1; // a ab bac acbd bdcae caedbf aecgbfdh
-    x % 2 // modulo 2
-}
+use crate::ldpc::FTX_LDPC_CODE;
+use crate::protocol::{Protocol, FT4, FT8};
 
 // Encode via LDPC a 91-bit message and return a 174-bit codeword.
-// The generator matrix has dimensions (87,87).
 // The code is a (174,91) regular LDPC code with column weight 3.
 // Arguments:
 // [IN] message   - array of 91 bits stored as 12 bytes (MSB first)
 // [OUT] codeword - array of 174 bits stored as 22 bytes (MSB first)
 fn encode174(message: &[u8; FTX_LDPC_K_BYTES], codeword: &mut [u8; FTX_LDPC_N_BYTES]) {
-    // This implementation accesses the generator bits straight from the packed binary representation in kFTX_LDPC_generator
-
-    // Fill the codeword with message and zeros, as we will only update binary ones later
-    for j in 0..FTX_LDPC_N_BYTES {
-        codeword[j] = if j < FTX_LDPC_K_BYTES { message[j] } else { 0 };
-    }
-
-    // Compute the byte index and bit mask for the first checksum bit
-    let mut col_mask = 0x80 >> (FTX_LDPC_K % 8); // bitmask of current byte
-    let mut col_idx = FTX_LDPC_K_BYTES - 1; // index into byte array
-
-    // Compute the LDPC checksum bits and store them in codeword
-    for i in 0..FTX_LDPC_M {
-        // Fast implementation of bitwise multiplication and parity checking
-        // Normally nsum would contain the result of dot product between message and kFTX_LDPC_generator[i],
-        // but we only compute the sum modulo 2.
-        let mut nsum = 0u8;
-
-        for (j, m) in message.iter().enumerate().take(FTX_LDPC_K_BYTES) {
-            let bits = m & FTX_LDPC_GENERATOR[i][j]; // bitwise AND (bitwise multiplication)
-            nsum ^= parity8(bits); // bitwise XOR (addition modulo 2)
-        }
-
-        // Set the current checksum bit in codeword if nsum is odd
-        if (nsum % 2) != 0 {
-            codeword[col_idx] |= col_mask;
-        }
-
-        // Update the byte index and bit mask for the next checksum bit
-        col_mask >>= 1;
-        if col_mask == 0 {
-            col_mask = 0x80;
-            col_idx += 1;
-        }
-    }
+    FTX_LDPC_CODE.encode(message, codeword);
 }
 
+// Encode a 77-bit payload into the channel symbols (tones) of the given mode.
+// tones must have space for protocol.num_symbols symbols.
+pub fn ftx_encode(protocol: &Protocol, payload: &[u8; FTX_LDPC_K_BYTES], tones: &mut [usize]) {
+    // FT4はペイロードをスクランブルしてからCRCを付加する
+    // 同じビットが続くメッセージでもトーンが偏らないようにするため
+    let mut payload_xor = *payload;
+    for (p, x) in payload_xor.iter_mut().zip(protocol.payload_xor) {
+        *p ^= x;
+    }
 
-pub fn ft8_encode(payload: &[u8; FTX_LDPC_K_BYTES], tones: &mut [usize; FT8_NN]) {
     let mut a91 = [0u8; FTX_LDPC_K_BYTES]; // Store 77 bits of payload + 14 bits CRC
 
     // Compute and add CRC at the end of the message
     // a91 contains 77 bits of payload + 14 bits of CRC
-    ftx_add_crc(payload, &mut a91);
+    ftx_add_crc(&payload_xor, &mut a91);
 
     let mut codeword = [0u8; FTX_LDPC_N_BYTES];
 
     encode174(&a91, &mut codeword);
 
-
-    // Message structure: S7 D29 S7 D29 S7
-    // Total symbols: 79 (FT8_NN)
+    // Message structure
+    // FT8: S7 D29 S7 D29 S7 (79 symbols)
+    // FT4: R S4 D29 S4 D29 S4 D29 S4 R (105 symbols)
 
     let mut mask = 0x80u8; // Mask to extract 1 bit from codeword
     let mut i_byte = 0usize; // Index of the current byte of the codeword
 
-    for i_tone in 0..FT8_NN {
-        if i_tone < 7 {
-            tones[i_tone] = FT8_COSTAS_PATTERN[i_tone];
-        } else if (36..43).contains(&i_tone) {
-            tones[i_tone] = FT8_COSTAS_PATTERN[i_tone - 36];
-        } else if (72..79).contains(&i_tone) {
-            tones[i_tone] = FT8_COSTAS_PATTERN[i_tone - 72];
+    for (i_tone, tone) in tones.iter_mut().enumerate().take(protocol.num_symbols) {
+        if protocol.is_ramp(i_tone) {
+            *tone = 0;
+        } else if let Some(sync) = protocol.sync_tone(i_tone) {
+            *tone = sync;
         } else {
-            // Extract 3 bits from codeword at i-th position
-            let mut bits3 = 0u8;
-
-            if (codeword[i_byte] & mask) != 0 {
-                bits3 |= 4;
+            // Extract bits_per_symbol bits from codeword at i-th position
+            let mut bits = 0usize;
+            for _ in 0..protocol.bits_per_symbol() {
+                bits <<= 1;
+                if (codeword[i_byte] & mask) != 0 {
+                    bits |= 1;
+                }
+
+                mask >>= 1;
+                if mask == 0 {
+                    mask = 0x80u8;
+                    i_byte += 1;
+                }
             }
-
-            mask >>= 1;
-            if mask == 0 {
-                mask = 0x80u8;
-                i_byte += 1;
-            }
-
-            if (codeword[i_byte] & mask) != 0 {
-                bits3 |= 2;
-            }
-
-            mask >>= 1;
-            if mask == 0 {
-                mask = 0x80u8;
-                i_byte += 1;
-            }
-
-            if (codeword[i_byte] & mask) != 0 {
-                bits3 |= 1;
-            }
-
-            mask >>= 1;
-            if mask == 0 {
-                mask = 0x80u8;
-                i_byte += 1
-            }
-            //3bitを8つのトーンに変換
+            //ビット列をトーンに変換
             //グレイコードになっているので隣のトーンのビットパターンとのハミング距離(ビットが相違する数)は1になり
             //ドップラー等で周波数が変わってしまった場合でも誤り訂正で修正できる可能性が高い
-            tones[i_tone] = FT8_GRAY_MAP[bits3 as usize];
+            *tone = protocol.gray_map[bits];
         }
     }
 }
+
+pub fn ft8_encode(payload: &[u8; FTX_LDPC_K_BYTES], tones: &mut [usize; FT8_NN]) {
+    ftx_encode(&FT8, payload, tones);
+}
+
+pub fn ft4_encode(payload: &[u8; FTX_LDPC_K_BYTES], tones: &mut [usize; FT4_NN]) {
+    ftx_encode(&FT4, payload, tones);
+}
+#[cfg(test)]
+#[path = "../test/test_util.rs"]
+mod test_util;
+
 #[cfg(test)]
 mod tests {
-    mod test_utils;
+    use super::test_util::unpack_bits;
+    use super::*;
     use crate::ldpc::*;
-    use crate::test_utils::*;
-    /* 
-    let mut codeword_bits = [0u8; FTX_LDPC_N];
-    unpack_bits(&codeword, &mut codeword_bits);
-    print!("check sum errors = {}\n", ldpc_check(&codeword_bits));
-    print!("Raw FT8 codewards = {:?}\n",codeword_bits);
-    */
-}
\ No newline at end of file
+
+    #[test]
+    fn codeword_satisfies_parity_checks() {
+        let payload = [
+            0x00, 0x00, 0x00, 0x20, 0x4d, 0xb5, 0x66, 0xd3, 0x5e, 0x08, 0x00, 0x00,
+        ];
+        let mut a91 = [0u8; FTX_LDPC_K_BYTES];
+        ftx_add_crc(&payload, &mut a91);
+
+        let mut codeword = [0u8; FTX_LDPC_N_BYTES];
+        encode174(&a91, &mut codeword);
+
+        let mut codeword_bits = [0u8; FTX_LDPC_N];
+        unpack_bits(&codeword, &mut codeword_bits);
+        assert_eq!(ldpc_check(&codeword_bits), 0);
+    }
+}
@@ -1,7 +1,7 @@
-use crate::constant::FT8_NN;
-use crate::spectrogram::*;
+use rustfft::num_complex::Complex;
 
 pub const FT8_SYMBOL_BT: f32 = 2.0f32;
+pub const FT4_SYMBOL_BT: f32 = 1.0f32;
 ///< symbol smoothing filter bandwidth factor (BT
 const GFSK_CONST_K: f32 = 5.336446f32;
 ///< == pi * sqrt(2 / log(2))
@@ -27,9 +27,173 @@ pub fn gfsk_pulse(n_spsym: usize, mut symbol_bt: f32, pulse: &mut [f32]) {
     }
 }
 
+/// Streaming GFSK modulator.
+/// The pulse is computed once in new(). fill() writes the waveform into caller-provided
+/// chunks of any size with continuous phase, without allocating or touching the filesystem,
+/// so it can be called from an audio callback.
+pub struct GfskModulator {
+    n_spsym: usize, // Samples per symbol
+    signal_rate: f32,
+    dphi_peak: f32,
+    pulse: Vec<f32>,
+    symbols: Vec<usize>,
+    f0: f32,
+    drift: f32,
+    t_mid: f32,
+    n_ramp: usize,
+    pos: usize,  // Next output sample
+    end: usize,  // End of the output, earlier than the frame end after stop()
+    fade: usize, // Length of the ramp-down before end
+    phi: f32,
+}
+
+impl GfskModulator {
+    /// @param[in] symbol_bt Symbol smoothing filter bandwidth (2 for FT8, 1 for FT4)
+    /// @param[in] symbol_period Symbol period (duration), seconds
+    /// @param[in] signal_rate Sample rate of synthesized signal, Hertz
+    pub fn new(symbol_bt: f32, symbol_period: f32, signal_rate: f32) -> Self {
+        let n_spsym = (0.5 + signal_rate * symbol_period) as usize;
+        let hmod = 1.0f32;
+        let mut pulse = vec![0.0; 3 * n_spsym];
+        gfsk_pulse(n_spsym, symbol_bt, &mut pulse);
+        GfskModulator {
+            n_spsym,
+            signal_rate,
+            dphi_peak: 2.0 * PI * hmod / n_spsym as f32,
+            pulse,
+            symbols: Vec::new(),
+            f0: 0.0,
+            drift: 0.0,
+            t_mid: 0.0,
+            n_ramp: if cfg!(feature = "disable_gfsk_ramp") {
+                0
+            } else {
+                n_spsym / 8
+            },
+            pos: 0,
+            end: 0,
+            fade: 0,
+            phi: 0.0,
+        }
+    }
+
+    /// Start a new frame. The symbol buffer is reused, so this allocates only when
+    /// the frame is longer than any previous one.
+    /// @param[in] symbols Array of symbols (tones) (0-7 for FT8, 0-3 for FT4)
+    /// @param[in] f0 Audio frequency in Hertz for the symbol 0 (base frequency)
+    /// @param[in] drift Frequency drift in Hertz per second. f0 is the frequency at the middle of the frame.
+    pub fn start(&mut self, symbols: &[usize], f0: f32, drift: f32) {
+        self.symbols.clear();
+        self.symbols.extend_from_slice(symbols);
+        self.f0 = f0;
+        self.drift = drift;
+        self.t_mid = (self.num_samples() / 2 + self.n_spsym) as f32 / self.signal_rate;
+        self.pos = 0;
+        self.end = self.num_samples();
+        self.fade = self.n_ramp;
+        self.phi = 0.0;
+    }
+
+    /// End the frame early, ramping down over the next symbol instead of cutting off.
+    pub fn stop(&mut self) {
+        let end = self.pos + self.n_spsym;
+        if end < self.end {
+            self.end = end;
+            self.fade = self.n_spsym;
+        }
+    }
+
+    /// Samples in the whole frame
+    pub fn num_samples(&self) -> usize {
+        self.symbols.len() * self.n_spsym
+    }
+
+    /// Samples not yet written
+    pub fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pos >= self.end
+    }
+
+    /// Write the next samples of the frame into out and return how many were written.
+    /// The rest of out is filled with silence once the frame has ended.
+    pub fn fill(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.remaining());
+        for v in out.iter_mut().take(count) {
+            let (phi, env) = self.step();
+            *v = phi.sin() * env;
+        }
+        for v in out.iter_mut().skip(count) {
+            *v = 0.0;
+        }
+        count
+    }
+
+    /// Same as fill, producing complex baseband (I/Q) samples.
+    /// f0 may be zero or negative, e.g. to centre the tones on 0 Hz.
+    pub fn fill_iq(&mut self, out: &mut [Complex<f32>]) -> usize {
+        let count = out.len().min(self.remaining());
+        for v in out.iter_mut().take(count) {
+            let (phi, env) = self.step();
+            *v = Complex::new(phi.cos() * env, phi.sin() * env);
+        }
+        for v in out.iter_mut().skip(count) {
+            *v = Complex::new(0.0, 0.0);
+        }
+        count
+    }
+
+    // Phase and envelope of the next sample
+    fn step(&mut self) -> (f32, f32) {
+        let k = self.pos;
+        let phi = self.phi;
+        // Apply envelope shaping to the first and last symbols
+        let mut env = 1.0;
+        if k < self.n_ramp {
+            env *= ramp(k, self.n_ramp);
+        }
+        if self.end - 1 - k < self.fade {
+            env *= ramp(self.end - 1 - k, self.fade);
+        }
+        self.phi = libm::fmodf(self.phi + self.dphi(k + self.n_spsym), 2.0 * PI);
+        self.pos += 1;
+        (phi, env)
+    }
+
+    // Smoothed phase increment at sample m of the frame extended by one symbol at each end.
+    // The extra symbols repeat the first and last tones.
+    fn dphi(&self, m: usize) -> f32 {
+        let n = self.n_spsym;
+        let n_sym = self.symbols.len();
+        // Shift frequency up by f0 (plus the drift at each sample)
+        let f = self.f0 + self.drift * (m as f32 / self.signal_rate - self.t_mid);
+        let mut dphi = 2.0 * PI * f / self.signal_rate;
+
+        // 3シンボル長のパルスが重なるのは直前2シンボルまで
+        let q = m / n;
+        for i in q.saturating_sub(2)..=q.min(n_sym - 1) {
+            dphi += self.dphi_peak * self.symbols[i] as f32 * self.pulse[m - i * n];
+        }
+        if m < 2 * n {
+            dphi += self.dphi_peak * self.pulse[m + n] * self.symbols[0] as f32;
+        }
+        if q >= n_sym {
+            dphi += self.dphi_peak * self.pulse[m - n_sym * n] * self.symbols[n_sym - 1] as f32;
+        }
+        dphi
+    }
+}
+
+// Raised-cosine envelope at sample i of an n-sample ramp
+fn ramp(i: usize, n: usize) -> f32 {
+    (1.0 - (2.0 * PI * i as f32 / (2.0 * n as f32)).cos()) / 2.0
+}
+
 /// Synthesize waveform data using GFSK phase shaping.
 /// The output waveform will contain n_sym symbols.
-/// @param[in] symbols Array of symbols (tones) (0-7 for FT8)
+/// @param[in] symbols Array of symbols (tones) (0-7 for FT8, 0-3 for FT4)
 /// @param[in] n_sym Number of symbols in the symbol array
 /// @param[in] f0 Audio frequency in Hertz for the symbol 0 (base frequency)
 /// @param[in] symbol_bt Symbol smoothing filter bandwidth (2 for FT8, 1 for FT4)
@@ -38,7 +202,7 @@ pub fn gfsk_pulse(n_spsym: usize, mut symbol_bt: f32, pulse: &mut [f32]) {
 /// @param[out] signal Output array of signal waveform samples (should have space for n_sym*n_spsym samples)
 ///
 pub fn synth_gfsk(
-    symbols: &[usize; FT8_NN],
+    symbols: &[usize],
     n_sym: usize,
     f0: f32,
     symbol_bt: f32,
@@ -46,65 +210,116 @@ pub fn synth_gfsk(
     signal_rate: f32,
     signal: &mut [f32],
 ) {
-    let n_spsym = (0.5 + signal_rate * symbol_period) as usize; // Samples per symbol
-    let n_wave = n_sym * n_spsym; // Number of output samples
-    let hmod = 1.0f32;
-
-    // Compute the smoothed frequency waveform.
-    // Length = (nsym+2)*n_spsym samples, first and last symbols extended
-    let dphi_peak = 2.0 * PI * hmod / n_spsym as f32;
-    let mut dphi = Vec::new();
-
-    // Shift frequency up by f0
-    for _ in 0..(n_wave + 2 * n_spsym) {
-        dphi.push(2.0 * PI * f0 / signal_rate);
-    }
+    synth_gfsk_drift(
+        symbols,
+        n_sym,
+        f0,
+        0.0,
+        symbol_bt,
+        symbol_period,
+        signal_rate,
+        signal,
+    );
+}
 
-    let mut pulse = vec![0.0; 3 * n_spsym];
+/// Same as synth_gfsk, with the carrier drifting linearly during the transmission.
+/// @param[in] drift Frequency drift in Hertz per second. f0 is the frequency at the middle of the frame.
+#[allow(clippy::too_many_arguments)]
+pub fn synth_gfsk_drift(
+    symbols: &[usize],
+    n_sym: usize,
+    f0: f32,
+    drift: f32,
+    symbol_bt: f32,
+    symbol_period: f32,
+    signal_rate: f32,
+    signal: &mut [f32],
+) {
+    let mut modulator = GfskModulator::new(symbol_bt, symbol_period, signal_rate);
+    modulator.start(&symbols[..n_sym], f0, drift);
+    let n_wave = modulator.num_samples();
+    modulator.fill(&mut signal[..n_wave]);
+}
 
-    gfsk_pulse(n_spsym, symbol_bt, &mut pulse);
+/// Complex baseband (I/Q) variant of synth_gfsk.
+/// @param[in] f0 Frequency of the symbol 0 relative to the centre (0 Hz), Hertz
+/// @param[out] signal Output array of I/Q samples (should have space for n_sym*n_spsym samples)
+pub fn synth_gfsk_iq(
+    symbols: &[usize],
+    n_sym: usize,
+    f0: f32,
+    symbol_bt: f32,
+    symbol_period: f32,
+    signal_rate: f32,
+    signal: &mut [Complex<f32>],
+) {
+    let mut modulator = GfskModulator::new(symbol_bt, symbol_period, signal_rate);
+    modulator.start(&symbols[..n_sym], f0, 0.0);
+    let n_wave = modulator.num_samples();
+    modulator.fill_iq(&mut signal[..n_wave]);
+}
 
-    plot_graph(
-        "./gauss-envelope.png",
-        "GFSK Phase Envelope",
-        &pulse,
-        0,
-        pulse.len(),
-        0.0,
-        1.0,
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for (i, sym) in symbols.iter().enumerate().take(n_sym) {
-        let ib = i * n_spsym;
-        for j in 0..3 * n_spsym {
-            dphi[j + ib] += dphi_peak * (*sym as f32) * pulse[j];
+    #[test]
+    fn chunks_match_whole_frame() {
+        let symbols: Vec<usize> = (0..79).map(|i| (i * 5 + 3) % 8).collect();
+        let mut whole = vec![0.0; 79 * 1920];
+        synth_gfsk_drift(&symbols, 79, 1000.0, 0.5, 2.0, 0.16, 12000.0, &mut whole);
+
+        // 480-sample audio callbacks, the modulator reused for two frames
+        let mut modulator = GfskModulator::new(2.0, 0.16, 12000.0);
+        for _ in 0..2 {
+            modulator.start(&symbols, 1000.0, 0.5);
+            let mut chunked = Vec::new();
+            let mut chunk = [1.0f32; 480];
+            while !modulator.is_done() {
+                let n = modulator.fill(&mut chunk);
+                chunked.extend_from_slice(&chunk[..n]);
+            }
+            assert_eq!(chunked, whole);
+            assert_eq!(modulator.fill(&mut chunk), 0);
+            assert!(chunk.iter().all(|v| *v == 0.0));
         }
     }
 
-    plot_graph("./tones.png", "GFSK Tones", &dphi, 0, 16000, 0.625, 0.65);
-
-    // Add dummy symbols at beginning and end with tone values equal to 1st and last symbol, respectively
-    for j in 0..(2 * n_spsym) {
-        dphi[j] += dphi_peak * pulse[j + n_spsym] * symbols[0] as f32;
-        dphi[j + n_sym * n_spsym] += dphi_peak * pulse[j] * symbols[n_sym - 1] as f32;
-    }
+    #[test]
+    fn stop_ramps_down_over_one_symbol() {
+        let symbols: Vec<usize> = (0..79).map(|i| (i * 5 + 3) % 8).collect();
+        let mut modulator = GfskModulator::new(2.0, 0.16, 12000.0);
+        modulator.start(&symbols, 1000.0, 0.0);
+        let mut head = [0.0f32; 5000];
+        modulator.fill(&mut head);
+        modulator.stop();
+        modulator.stop();
+        assert_eq!(modulator.remaining(), 1920);
 
-    // Calculate and insert the audio waveform
-    let mut phi = 0.0f32;
-    for k in 0..n_wave {
-        // Don't include dummy symbols
-        signal[k] = phi.sin();
-        phi = libm::fmodf(phi + dphi[k + n_spsym], 2.0 * PI);
+        let mut tail = [1.0f32; 4000];
+        assert_eq!(modulator.fill(&mut tail), 1920);
+        assert!(modulator.is_done());
+        // Full amplitude at the start of the ramp, silent at its end
+        let peak = |s: &[f32]| s.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        assert!(peak(&tail[..100]) > 0.95);
+        assert!(peak(&tail[1820..1920]) < 0.01);
+        assert!(tail[1920..].iter().all(|v| *v == 0.0));
     }
 
-    // Apply envelope shaping to the first and last symbols
-    if cfg!(not(feature = "disable_gfsk_ramp")) {
-        let n_ramp = n_spsym / 8;
-        for i in 0..n_ramp {
-            let env = (1.0 - (2.0 * PI * i as f32 / (2.0 * n_ramp as f32)).cos()) / 2.0;
-            signal[i] *= env;
-            signal[n_wave - 1 - i] *= env;
+    #[test]
+    fn iq_matches_real_signal() {
+        let symbols: Vec<usize> = (0..105).map(|i| (i * 3 + 1) % 4).collect();
+        let n_wave = 105 * 576;
+        let mut real = vec![0.0; n_wave];
+        let mut iq = vec![Complex::new(0.0, 0.0); n_wave];
+        synth_gfsk(&symbols, 105, 1500.0, 1.0, 0.048, 12000.0, &mut real);
+        synth_gfsk_iq(&symbols, 105, 1500.0, 1.0, 0.048, 12000.0, &mut iq);
+        // The real signal is the imaginary part, with a constant envelope in the middle
+        for (r, z) in real.iter().zip(iq.iter()) {
+            assert_eq!(*r, z.im);
         }
+        assert!(iq[576..n_wave - 576]
+            .iter()
+            .all(|z| (z.norm() - 1.0).abs() < 1e-4));
     }
-    plot_graph("./signal.png", "GFSK Signal", signal, 0, 500, -1.5, 1.5);
 }
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::RwLock;
+
+// Characters allowed in a hashed callsign (same as charn() table 5)
+const A5: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ/";
+// Multiplier used by WSJT-X ihashcall()
+const HASH_MULTIPLIER: u64 = 47055833459;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashType {
+    Hash22,
+    Hash12,
+    Hash10,
+}
+
+impl HashType {
+    pub fn bits(&self) -> u32 {
+        match self {
+            HashType::Hash22 => 22,
+            HashType::Hash12 => 12,
+            HashType::Hash10 => 10,
+        }
+    }
+}
+
+// Compute the m-bit hash of a callsign as WSJT-X does.
+// The callsign is left-justified in 11 characters and read as a base-38 number.
+// Returns None if the callsign is too long or contains invalid characters.
+pub fn ihashcall(callsign: &str, hash_type: HashType) -> Option<u32> {
+    if callsign.len() > 11 {
+        return None;
+    }
+
+    let mut n8 = 0u64;
+    let mut chars = callsign.chars();
+    for _ in 0..11 {
+        let j = match chars.next() {
+            Some(c) => A5.find(c)?,
+            None => 0,
+        };
+        n8 = 38 * n8 + j as u64;
+    }
+
+    Some((n8.wrapping_mul(HASH_MULTIPLIER) >> (64 - hash_type.bits())) as u32)
+}
+
+// Table of callsigns heard or sent, indexed by their 22, 12 and 10-bit hashes.
+// Like WSJT-X, a new callsign replaces an older one with the same hash,
+// so a collision resolves to the most recently added callsign.
+// The table is shared between decoder threads, so it locks internally.
+#[derive(Debug, Default)]
+pub struct CallsignHashTable {
+    calls: RwLock<HashMap<(HashType, u32), String>>,
+}
+
+impl CallsignHashTable {
+    pub fn new() -> Self {
+        CallsignHashTable {
+            calls: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Register a full callsign. Angle brackets are stripped.
+    // Returns false if the callsign cannot be hashed.
+    pub fn add(&self, callsign: &str) -> bool {
+        let callsign = callsign
+            .trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        if callsign.is_empty() || callsign.starts_with("...") {
+            return false;
+        }
+        let Some(n22) = ihashcall(callsign, HashType::Hash22) else {
+            return false;
+        };
+        let mut calls = self.calls.write().unwrap();
+        // 12-bit and 10-bit hashes are the upper bits of the 22-bit one
+        for hash_type in [HashType::Hash22, HashType::Hash12, HashType::Hash10] {
+            let shift = HashType::Hash22.bits() - hash_type.bits();
+            calls.insert((hash_type, n22 >> shift), callsign.to_string());
+        }
+        true
+    }
+
+    // Look up a callsign by its hash.
+    pub fn lookup(&self, hash_type: HashType, hash: u32) -> Option<String> {
+        self.calls.read().unwrap().get(&(hash_type, hash)).cloned()
+    }
+
+    // Look up a hash and format it the way it appears in a message.
+    pub fn lookup_bracketed(&self, hash_type: HashType, hash: u32) -> String {
+        match self.lookup(hash_type, hash) {
+            Some(call) => format!("<{}>", call),
+            None => "<...>".to_string(),
+        }
+    }
+
+    // Number of callsigns with distinct 22-bit hashes
+    pub fn len(&self) -> usize {
+        let calls = self.calls.read().unwrap();
+        calls.keys().filter(|(t, _)| *t == HashType::Hash22).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Load a table saved by save(). A missing file yields an empty table.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let table = CallsignHashTable::new();
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(table),
+            Err(e) => return Err(e),
+        };
+        for line in BufReader::new(file).lines() {
+            table.add(&line?);
+        }
+        Ok(table)
+    }
+
+    // Save the table as one callsign per line, creating the directory if needed.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if let Some(dir) = path.as_ref().parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut calls: Vec<String> = self
+            .calls
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((t, _), _)| *t == HashType::Hash22)
+            .map(|(_, call)| call.clone())
+            .collect();
+        calls.sort();
+        let mut out = BufWriter::new(File::create(path)?);
+        for call in calls {
+            writeln!(out, "{}", call)?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_all_hash_sizes() {
+        let table = CallsignHashTable::new();
+        assert!(table.add("<PJ4/K1ABC>"));
+
+        let n22 = ihashcall("PJ4/K1ABC", HashType::Hash22).unwrap();
+        let n12 = ihashcall("PJ4/K1ABC", HashType::Hash12).unwrap();
+        let n10 = ihashcall("PJ4/K1ABC", HashType::Hash10).unwrap();
+        assert_eq!(n12, n22 >> 10);
+        assert_eq!(n10, n22 >> 12);
+
+        assert_eq!(table.lookup_bracketed(HashType::Hash22, n22), "<PJ4/K1ABC>");
+        assert_eq!(table.lookup_bracketed(HashType::Hash12, n12), "<PJ4/K1ABC>");
+        assert_eq!(table.lookup_bracketed(HashType::Hash10, n10), "<PJ4/K1ABC>");
+        assert_eq!(table.lookup_bracketed(HashType::Hash22, n22 ^ 1), "<...>");
+    }
+
+    #[test]
+    fn persists_between_runs() {
+        let path = std::env::temp_dir().join("rustft8_hashtable_test.txt");
+        let table = CallsignHashTable::new();
+        table.add("KH1/KH7Z");
+        table.add("JA1ABC");
+        table.save(&path).unwrap();
+
+        let loaded = CallsignHashTable::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.len(), 2);
+        let n12 = ihashcall("KH1/KH7Z", HashType::Hash12).unwrap();
+        assert_eq!(loaded.lookup(HashType::Hash12, n12).unwrap(), "KH1/KH7Z");
+    }
+
+    #[test]
+    fn collisions_resolve_to_latest() {
+        // Same 12-bit and 10-bit hash, different 22-bit hash
+        let n12 = ihashcall("PA3XYZ", HashType::Hash12).unwrap();
+        assert_eq!(ihashcall("G4ABC/P", HashType::Hash12), Some(n12));
+        let n10 = n12 >> 2;
+
+        let table = CallsignHashTable::new();
+        table.add("PA3XYZ");
+        table.add("G4ABC/P");
+        assert_eq!(table.lookup(HashType::Hash12, n12).unwrap(), "G4ABC/P");
+        assert_eq!(table.lookup(HashType::Hash10, n10).unwrap(), "G4ABC/P");
+        table.add("PA3XYZ");
+        assert_eq!(table.lookup(HashType::Hash12, n12).unwrap(), "PA3XYZ");
+        assert_eq!(table.lookup(HashType::Hash10, n10).unwrap(), "PA3XYZ");
+
+        // Both stay resolvable by their 22-bit hash
+        assert_eq!(table.len(), 2);
+        let n22 = ihashcall("G4ABC/P", HashType::Hash22).unwrap();
+        assert_eq!(table.lookup(HashType::Hash22, n22).unwrap(), "G4ABC/P");
+    }
+}
@@ -0,0 +1,52 @@
+use crate::error::Ft8Error;
+use rustfft::num_complex::Complex;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+// I/Q出力。SDRにそのまま渡せる形式で書き出す
+
+// Stereo 32-bit float WAV, I on the left and Q on the right channel
+pub fn write_iq_wav<P: AsRef<Path>>(
+    path: P,
+    sample_rate: u32,
+    iq: &[Complex<f32>],
+) -> Result<(), Ft8Error> {
+    let header = wav_io::new_header(sample_rate, 32, true, false);
+    let samples: Vec<f32> = iq.iter().flat_map(|z| [z.re, z.im]).collect();
+    let mut wav = File::create(path)?;
+    wav_io::write_to_file(&mut wav, &header, &samples)?;
+    Ok(())
+}
+
+// Raw interleaved little-endian f32 I/Q (cf32), as read by GNU Radio and SoapySDR tools
+pub fn write_cf32<W: Write>(out: &mut W, iq: &[Complex<f32>]) -> Result<(), Ft8Error> {
+    let mut bytes = Vec::with_capacity(iq.len() * 8);
+    for z in iq {
+        bytes.extend_from_slice(&z.re.to_le_bytes());
+        bytes.extend_from_slice(&z.im.to_le_bytes());
+    }
+    out.write_all(&bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iq_outputs_interleave() {
+        let iq = vec![Complex::new(0.5, -0.25), Complex::new(-1.0, 1.0)];
+        let mut raw = Vec::new();
+        write_cf32(&mut raw, &iq).unwrap();
+        assert_eq!(raw.len(), 16);
+        assert_eq!(&raw[4..8], &(-0.25f32).to_le_bytes());
+
+        let path = std::env::temp_dir().join("rustft8_iq_test.wav");
+        write_iq_wav(&path, 48000, &iq).unwrap();
+        let (header, samples) = wav_io::read_from_file(File::open(&path).unwrap()).unwrap();
+        assert_eq!((header.sample_rate, header.channels), (48000, 2));
+        assert_eq!(samples, vec![0.5, -0.25, -1.0, 1.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
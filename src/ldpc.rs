@@ -1,4 +1,5 @@
 use crate::constant::*;
+use std::sync::LazyLock;
 
 // Sum Productアルゴリズムで使われる
 // tanh/atanhを高速化するための近似
@@ -32,168 +33,511 @@ fn fast_atanh(x: f32) -> f32 {
     }
 }
 
-// codrewordの各ビットがLDPCの検査行列を満たすかチェック
-pub fn ldpc_check(codeword: &[u8; FTX_LDPC_N]) -> usize {
-    let mut errors: usize = 0;
-
-    //検査行列から1列取り出し
-    for m in FTX_LDPC_NM {
-        let mut x: u8 = 0;
-        for i in m {
-            if i != 0 {
-                //対応するcodeward中のbitのxorをとる
-                x ^= codeword[i - 1];
+// Parametric LDPC code: sparse parity-check matrix and systematic generator.
+// Codewords are the K message bits followed by the N-K parity bits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LdpcCode {
+    pub n: usize,                // Codeword bits
+    pub k: usize,                // Message bits
+    pub nm: Vec<Vec<usize>>,     // Codeword bits of each parity check (0-origin)
+    pub mn: Vec<Vec<usize>>,     // Parity checks of each codeword bit (0-origin)
+    pub generator: Vec<Vec<u8>>, // Row i computes parity bit i from the message, bitpacked (MSB first)
+    check_start: Vec<usize>,     // Offset of each check's edges in nm order (M+1 entries)
+    bit_start: Vec<usize>,       // Offset of each bit's edges in mn order (N+1 entries)
+}
+
+// Working memory of the decoders, reused between codewords so that
+// decoding does not allocate once the buffers have grown to the code size
+#[derive(Debug, Default)]
+pub struct LdpcScratch {
+    #[cfg(feature = "ldpc_bp")]
+    tov: Vec<f32>, // Check to bit messages, in mn order
+    #[cfg(feature = "ldpc_bp")]
+    toc: Vec<f32>, // Bit to check messages, in nm order
+    #[cfg(feature = "ldpc_bitflip")]
+    votes: Vec<[u32; 2]>, // Votes for 0 and 1 of each bit
+}
+
+// FT8/FT4 (174,91) code built from FTX_LDPC_NM and FTX_LDPC_GENERATOR
+pub static FTX_LDPC_CODE: LazyLock<LdpcCode> = LazyLock::new(|| {
+    let nm = FTX_LDPC_NM
+        .iter()
+        .map(|row| row.iter().filter(|&&i| i != 0).map(|i| i - 1).collect())
+        .collect();
+    let generator = FTX_LDPC_GENERATOR.iter().map(|row| row.to_vec()).collect();
+    LdpcCode::new(FTX_LDPC_N, FTX_LDPC_K, nm, generator)
+});
+
+impl LdpcCode {
+    // mn is derived from nm, each bit listing its checks in ascending order
+    pub fn new(n: usize, k: usize, nm: Vec<Vec<usize>>, generator: Vec<Vec<u8>>) -> Self {
+        assert_eq!(generator.len(), n - k, "one generator row per parity bit");
+        let mut mn = vec![Vec::new(); n];
+        for (m, row) in nm.iter().enumerate() {
+            for &i in row {
+                mn[i].push(m);
             }
         }
-        //偶数パリティであればこの行はOK
-        if x != 0 {
-            errors += 1;
+        let offsets = |rows: &[Vec<usize>]| -> Vec<usize> {
+            let mut start = vec![0];
+            for row in rows {
+                start.push(start[start.len() - 1] + row.len());
+            }
+            start
+        };
+        LdpcCode {
+            n,
+            k,
+            check_start: offsets(&nm),
+            bit_start: offsets(&mn),
+            nm,
+            mn,
+            generator,
         }
     }
-    //すべての行を満たす(errors = 0)なら検査行列を満たす
-    errors
-}
 
-//
-// 積和アルゴリズムによるデコーダの実装
-//
-#[cfg(feature = "ldpc_bp")]
-pub fn ldpc_decode(
-    codeward: [f32; FTX_LDPC_N],
-    max_iters: i32,
-    plain: &mut [u8; FTX_LDPC_N],
-) -> usize {
-    //検査メッセージEを初期化
-    let mut tov = [[0.0f32; 7]; FTX_LDPC_N];
-    //ビットメッセージを初期化
-    let mut toc = [[0.0f32; 7]; FTX_LDPC_M];
-    //最小エラー数を取りうる最大値で初期化
-    let mut min_errors = FTX_LDPC_M;
-
-    //積和アルゴリズムの繰り返し回数分をループ
-    for _it in 0..max_iters {
-        let mut plain_sum: u8 = 0;
-        //(1) テスト
-        for n in 0..FTX_LDPC_N {
-            //対数尤度で示されたcodewardの各ビットを検査メッセージEで更新
-            //(codewardの1ビットについて3つのチェックノードからの検査メッセージが来る)
-            //対数尤度 Log(P(c=1)/P(c=0))で判定しているのでP(c=1)>P(c=0)なら'1'
-            //P(c=1)<P(c=0)なら'0'と判定しplain[n]へ格納
-            plain[n] = if (codeward[n] + tov[n][0] + tov[n][1] + tov[n][2]) > 0.0f32 {
-                1
-            } else {
-                0
+    // Build a systematic code from a parity-check matrix (one row per check) by
+    // Gauss-Jordan elimination over GF(2). Pivots are taken from the rightmost columns,
+    // so a matrix whose last N-K columns are independent keeps its bit order.
+    // Otherwise the pivot columns are moved to the end, giving an equivalent code.
+    // Redundant checks are kept in nm; K is N minus the rank.
+    pub fn from_parity_check(h: &[Vec<u32>]) -> Self {
+        let n = h.first().map_or(0, |row| row.len());
+        let mut rows: Vec<Vec<u8>> = h
+            .iter()
+            .map(|row| row.iter().map(|&b| (b & 1) as u8).collect())
+            .collect();
+
+        //右の列からピボットを選んで掃き出す
+        let mut pivots = Vec::new(); // (column, row)
+        for col in (0..n).rev() {
+            let row = pivots.len();
+            let Some(found) = (row..rows.len()).find(|&r| rows[r][col] == 1) else {
+                continue;
             };
-            plain_sum += plain[n];
-        }
-        //すべてのbitが0の場合は再度繰り返し
-        if plain_sum == 0 {
-            break;
-        }
-        //得られたメッセージ列が検査行列を満たすかチェック
-        let errors = ldpc_check(plain);
-        //パリティエラー数の最小値を更新
-        if errors < min_errors {
-            min_errors = errors;
-            //すべてのビットでエラーがなければデコード完了
-            if errors == 0 {
-                break;
+            rows.swap(row, found);
+            for r in 0..rows.len() {
+                if r != row && rows[r][col] == 1 {
+                    let pivot_row = rows[row].clone();
+                    for (x, p) in rows[r].iter_mut().zip(pivot_row.iter()) {
+                        *x ^= p;
+                    }
+                }
             }
+            pivots.push((col, row));
         }
-        //(2)ビットメッセージの更新
-        //各検査ノードmに接続するビットノードnからのビットメッセージMを更新する
-        //各検査ノードmがビットノードからみて0/1の何れの確率が高いか対数尤度で求める
-        for m in 0..FTX_LDPC_M {
-            //検査行列の各行の要素を取り出す
-            for (n_idx, &n) in FTX_LDPC_NM[m].iter().enumerate() {
-                if n != 0 {
-                    //検査ノードに接続するビットノードnを求め
-                    let n = n - 1;
+        pivots.sort();
+
+        //ピボット以外の列がメッセージビット、ピボット列がパリティビット
+        let k = n - pivots.len();
+        let mut order: Vec<usize> = (0..n)
+            .filter(|c| pivots.binary_search_by_key(c, |(col, _)| *col).is_err())
+            .collect();
+        order.extend(pivots.iter().map(|(col, _)| *col));
+        let mut position = vec![0; n];
+        for (new, &old) in order.iter().enumerate() {
+            position[old] = new;
+        }
+
+        //パリティビット i = 掃き出し後の行のメッセージ部分とメッセージの内積
+        let generator = pivots
+            .iter()
+            .map(|(_, row)| {
+                let mut packed = vec![0u8; k.div_ceil(8)];
+                for (j, &col) in order.iter().take(k).enumerate() {
+                    if rows[*row][col] == 1 {
+                        packed[j / 8] |= 0x80 >> (j % 8);
+                    }
+                }
+                packed
+            })
+            .collect();
+        let nm = h
+            .iter()
+            .map(|row| {
+                let mut bits: Vec<usize> = (0..n)
+                    .filter(|&c| row[c] & 1 == 1)
+                    .map(|c| position[c])
+                    .collect();
+                bits.sort();
+                bits
+            })
+            .collect();
+        LdpcCode::new(n, k, nm, generator)
+    }
+
+    // Rust source of the code tables, in the same format as FTX_LDPC_GENERATOR,
+    // FTX_LDPC_NM and FTX_LDPC_MN in constant.rs (prefix e.g. "FTX_LDPC")
+    pub fn to_tables(&self, prefix: &str) -> String {
+        let (n, k, m) = (self.n, self.k, self.m());
+        let mut out = String::new();
+        out.push_str(&format!("// ({},{}) LDPC code\n", n, k));
+        out.push_str(&format!("pub const {}_N: usize = {};\n", prefix, n));
+        out.push_str(&format!("pub const {}_K: usize = {};\n", prefix, k));
+        out.push_str(&format!("pub const {}_M: usize = {};\n\n", prefix, m));
+
+        out.push_str(&format!(
+            "// Parity generator matrix for ({},{}) LDPC code, stored in bitpacked format (MSB first)\n",
+            n, k
+        ));
+        out.push_str(&format!(
+            "pub const {}_GENERATOR: [[u8; {}]; {}] = [\n",
+            prefix,
+            self.k_bytes(),
+            n - k
+        ));
+        for row in self.generator.iter() {
+            out.push_str("    [\n");
+            for chunk in row.chunks(12) {
+                let bytes: Vec<String> = chunk.iter().map(|b| format!("0x{:02x},", b)).collect();
+                out.push_str(&format!("        {}\n", bytes.join(" ")));
+            }
+            out.push_str("    ],\n");
+        }
+        out.push_str("];\n\n");
+
+        //1-originで、次数が足りない分は0で埋める
+        let one_origin = |rows: &[Vec<usize>]| -> (usize, Vec<String>) {
+            let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+            let lines = rows
+                .iter()
+                .map(|r| {
+                    let mut v: Vec<String> = r.iter().map(|i| (i + 1).to_string()).collect();
+                    v.resize(width, "0".to_string());
+                    format!("    [{}],\n", v.join(", "))
+                })
+                .collect();
+            (width, lines)
+        };
+        let (width, lines) = one_origin(&self.nm);
+        out.push_str("// Parity Check Equations.\n");
+        out.push_str("// Each row corresponds to parity check matrix column.\n");
+        out.push_str("// The numbers indicate the codeword bit. 1-origin.\n");
+        out.push_str(&format!(
+            "pub const {}_NM: [[usize; {}]; {}] = [\n",
+            prefix, width, m
+        ));
+        lines.iter().for_each(|l| out.push_str(l));
+        out.push_str("];\n\n");
+
+        let (width, lines) = one_origin(&self.mn);
+        out.push_str("// Each row corresponds to a codeword bit.\n");
+        out.push_str("// The numbers indicate which LDPC parity checks (rows in Nm) refer to the codeword bit.\n");
+        out.push_str("// 1-origin.\n");
+        out.push_str(&format!(
+            "pub const {}_MN: [[usize; {}]; {}] = [\n",
+            prefix, width, n
+        ));
+        lines.iter().for_each(|l| out.push_str(l));
+        out.push_str("];\n");
+        out
+    }
+
+    // Number of parity checks
+    pub fn m(&self) -> usize {
+        self.nm.len()
+    }
+
+    pub fn k_bytes(&self) -> usize {
+        self.k.div_ceil(8)
+    }
+
+    pub fn n_bytes(&self) -> usize {
+        self.n.div_ceil(8)
+    }
+
+    // Encode k_bytes() of message (MSB first) into n_bytes() of codeword
+    pub fn encode(&self, message: &[u8], codeword: &mut [u8]) {
+        let k_bytes = self.k_bytes();
+        codeword[..self.n_bytes()].fill(0);
+        codeword[..k_bytes].copy_from_slice(&message[..k_bytes]);
+        // メッセージの最後のバイトの余りビットはパリティ用に空けておく
+        if !self.k.is_multiple_of(8) {
+            codeword[k_bytes - 1] &= 0xff << (8 - self.k % 8);
+        }
+
+        for (i, generator) in self.generator.iter().enumerate() {
+            // 生成行列の行とメッセージの内積(mod 2)がパリティビット
+            let nsum = message
+                .iter()
+                .zip(generator.iter())
+                .fold(0, |acc, (m, g)| acc ^ (m & g).count_ones());
+            if nsum % 2 != 0 {
+                let pos = self.k + i;
+                codeword[pos / 8] |= 0x80 >> (pos % 8);
+            }
+        }
+    }
+
+    // codrewordの各ビットがLDPCの検査行列を満たすかチェック
+    // Returns the number of unsatisfied checks (codeword holds one bit per byte)
+    pub fn check(&self, codeword: &[u8]) -> usize {
+        //検査行列から1行取り出し、対応するcodeword中のbitのxorをとる
+        //偶数パリティでない行の数を数える
+        self.nm
+            .iter()
+            .filter(|m| m.iter().fold(0, |x, &i| x ^ codeword[i]) != 0)
+            .count()
+    }
+
+    //
+    // 積和アルゴリズムによるデコーダの実装
+    //
+    // llr is log(P(x=1)/P(x=0)) of each codeword bit. Returns the number of parity errors.
+    #[cfg(feature = "ldpc_bp")]
+    pub fn decode(
+        &self,
+        codeward: &[f32],
+        max_iters: i32,
+        plain: &mut [u8],
+        scratch: &mut LdpcScratch,
+    ) -> usize {
+        //検査メッセージEを初期化
+        let tov = &mut scratch.tov;
+        tov.clear();
+        tov.resize(self.bit_start[self.n], 0.0f32);
+        //ビットメッセージを初期化
+        let toc = &mut scratch.toc;
+        toc.clear();
+        toc.resize(self.check_start[self.m()], 0.0f32);
+        //最小エラー数を取りうる最大値で初期化
+        let mut min_errors = self.m();
+
+        //積和アルゴリズムの繰り返し回数分をループ
+        for _it in 0..max_iters {
+            let mut plain_sum: usize = 0;
+            //(1) テスト
+            for n in 0..self.n {
+                //対数尤度で示されたcodewardの各ビットを検査メッセージEで更新
+                //対数尤度 Log(P(c=1)/P(c=0))で判定しているのでP(c=1)>P(c=0)なら'1'
+                //P(c=1)<P(c=0)なら'0'と判定しplain[n]へ格納
+                let tov_n = &tov[self.bit_start[n]..self.bit_start[n + 1]];
+                let sum = tov_n.iter().fold(codeward[n], |acc, e| acc + e);
+                plain[n] = if sum > 0.0f32 { 1 } else { 0 };
+                plain_sum += plain[n] as usize;
+            }
+            //すべてのbitが0の場合は再度繰り返し
+            if plain_sum == 0 {
+                break;
+            }
+            //得られたメッセージ列が検査行列を満たすかチェック
+            let errors = self.check(plain);
+            //パリティエラー数の最小値を更新
+            if errors < min_errors {
+                min_errors = errors;
+                //すべてのビットでエラーがなければデコード完了
+                if errors == 0 {
+                    break;
+                }
+            }
+            //(2)ビットメッセージの更新
+            //各検査ノードmに接続するビットノードnからのビットメッセージMを更新する
+            for (m, checks) in self.nm.iter().enumerate() {
+                for (n_idx, &n) in checks.iter().enumerate() {
                     //受信したcodeward[n](ビット位置n)の値を初期値とし
                     let mut tnm = codeward[n];
                     //ビットノードnの検査メッセージEとの和をとる（ただしノードmから来たメッセージは除く）
-                    for m_idx in 0..3 {
-                        if (FTX_LDPC_MN[n][m_idx] - 1) != m {
-                            tnm += tov[n][m_idx];
+                    for (m_idx, &mm) in self.mn[n].iter().enumerate() {
+                        if mm != m {
+                            tnm += tov[self.bit_start[n] + m_idx];
                         }
                     }
                     //E = - 2 * atan(Π tanh(-M/2))のtanh(-M/2)の部分
-                    toc[m][n_idx] = fast_tanh(-tnm / 2.0f32);
+                    toc[self.check_start[m] + n_idx] = fast_tanh(-tnm / 2.0f32);
                 }
             }
-        }
-        //(3)検査メッセージの更新
-        //各ビットノードnに接続する検査ノードmからの検査メッセージEを更新する
-        //各ビットノードnが検査ノードからみて0/1の何れかの確率が高いか対数尤度で求める
-        for n in 0..FTX_LDPC_N {
-            for m_idx in 0..3 {
-                //ビットノードnに接続する検査ノードmを求め
-                let m = FTX_LDPC_MN[n][m_idx] - 1;
-                let mut tmn = 1.0f32;
-                //検査ノードmのビットメッセージMの積を求める
-                for (n_idx, &nn) in FTX_LDPC_NM[m].iter().enumerate() {
-                    if (nn != 0) && (nn - 1) != n {
-                        tmn *= toc[m][n_idx];
+            //(3)検査メッセージの更新
+            //各ビットノードnに接続する検査ノードmからの検査メッセージEを更新する
+            for (n, bits) in self.mn.iter().enumerate() {
+                for (m_idx, &m) in bits.iter().enumerate() {
+                    let mut tmn = 1.0f32;
+                    //検査ノードmのビットメッセージMの積を求める
+                    for (n_idx, &nn) in self.nm[m].iter().enumerate() {
+                        if nn != n {
+                            tmn *= toc[self.check_start[m] + n_idx];
+                        }
                     }
+                    // 検査メッセージ E = -2 + atan(Π tanh(-M/2))
+                    tov[self.bit_start[n] + m_idx] = -2.0f32 * fast_atanh(tmn);
+                }
+            }
+        }
+        min_errors
+    }
+
+    //
+    //  ビットフリップアルゴリズムによるデコーダの実装
+    //
+    #[cfg(feature = "ldpc_bitflip")]
+    pub fn decode(
+        &self,
+        codeward: &[f32],
+        max_iters: i32,
+        plain: &mut [u8],
+        scratch: &mut LdpcScratch,
+    ) -> usize {
+        // 軟判定(log (P(x=1) / P(x=0)))を硬判定(0/1)に変換
+        for (p, x) in plain.iter_mut().zip(codeward.iter()).take(self.n) {
+            *p = if *x >= 0.0 { 1 } else { 0 };
+        }
+
+        for _ in 0..max_iters {
+            //codeword中の各ビットが各チェックノードの判定で0又は1何れが多いか判定
+            let votes = &mut scratch.votes;
+            votes.clear();
+            votes.resize(self.n, [0; 2]);
+
+            //チェックノードの要素を取り出す
+            for e in self.nm.iter() {
+                //チェックノードから接続するビットノードbiについてパリティを計算
+                for &bi in e {
+                    //ビットノードbi以外のビットノードとxorをとる
+                    let x = e
+                        .iter()
+                        .filter(|&&i| i != bi)
+                        .fold(0, |x, &i| x ^ plain[i]);
+                    //x = 0ならノードbiは0、x = 1ならノードbiは1に一票
+                    votes[bi][x as usize] += 1;
                 }
-                // 検査メッセージ E = -2 + atan(Π tanh(-M/2))
-                tov[n][m_idx] = -2.0f32 * fast_atanh(tmn);
+            }
+            // 投票結果にもとづきデコード結果plainの各ビットを更新
+            for i in 0..self.n {
+                if plain[i] == 0 && (votes[i][1] > votes[i][0]) {
+                    plain[i] = 1;
+                } else if plain[i] == 1 && (votes[i][0] > votes[i][1]) {
+                    plain[i] = 0;
+                }
+            }
+            //　検査行列を満たすかチェック
+            if self.check(plain) == 0 {
+                return 0;
             }
         }
+        //所定の繰り返しで終わらなければエラー
+        1
     }
-    min_errors
 }
 
-//
-//  ビットフリップアルゴリズムによるデコーダの実装
-//
-#[cfg(feature = "ldpc_bitflip")]
+// codrewordの各ビットが(174,91)符号の検査行列を満たすかチェック
+pub fn ldpc_check(codeword: &[u8; FTX_LDPC_N]) -> usize {
+    FTX_LDPC_CODE.check(codeword)
+}
+
+// Decode with the (174,91) code
+#[cfg(any(feature = "ldpc_bp", feature = "ldpc_bitflip"))]
 pub fn ldpc_decode(
     codeward: [f32; FTX_LDPC_N],
     max_iters: i32,
     plain: &mut [u8; FTX_LDPC_N],
+    scratch: &mut LdpcScratch,
 ) -> usize {
-    // 軟判定(log (P(x=1) / P(x=0)))を硬判定(0/1)に変換
-    plain.copy_from_slice(&codeward.map(|x| if x >= 0.0 { 1u8 } else { 0 }));
-
-    for _ in 0..max_iters {
-        //codeword中の各ビットが各チェックノードの判定で0又は1何れが多いか判定
-        let mut votes = vec![vec![0; 2]; FTX_LDPC_N];
-
-        //チェックノードの要素を取り出す
-        for e in FTX_LDPC_NM {
-            //チェックノードから接続するビットノードbiについてパリティを計算
-            for bi in e {
-                if bi == 0 {
-                    continue;
-                }
-                let mut x = 0;
-                //ビットノードbi以外のビットノードとxorをとる
-                for i in e {
-                    if i != 0 && i != bi {
-                        x ^= plain[i - 1];
-                    }
-                }
-                //チェックサムの結果にもとづきビットノードbiのあるべき値を投票
-                //x = 0ならノードbiは0、x = 1ならノードbiは1に一票
-                votes[bi - 1][x as usize] += 1;
-            }
+    FTX_LDPC_CODE.decode(&codeward, max_iters, plain, scratch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (7,4) Hamming code
+    fn hamming() -> LdpcCode {
+        let nm = vec![vec![0, 1, 3, 4], vec![0, 2, 3, 5], vec![1, 2, 3, 6]];
+        let generator = vec![vec![0b1101_0000], vec![0b1011_0000], vec![0b0111_0000]];
+        LdpcCode::new(7, 4, nm, generator)
+    }
+
+    fn bits(code: &LdpcCode, codeword: &[u8]) -> Vec<u8> {
+        (0..code.n)
+            .map(|i| (codeword[i / 8] >> (7 - i % 8)) & 1)
+            .collect()
+    }
+
+    #[test]
+    fn ft8_code_matches_tables() {
+        let code = &*FTX_LDPC_CODE;
+        assert_eq!((code.n, code.k, code.m()), (FTX_LDPC_N, FTX_LDPC_K, FTX_LDPC_M));
+        for (n, checks) in FTX_LDPC_MN.iter().enumerate() {
+            let expected: Vec<usize> = checks.iter().map(|m| m - 1).collect();
+            assert_eq!(code.mn[n], expected);
         }
-        // 投票結果にもとづきデコード結果plainの各ビットを更新
-        for i in 0..FTX_LDPC_N {
-            //対象とするbitが0で投票結果が1の方が多いなら1に反転
-            if plain[i] == 0 && (votes[i][1] > votes[i][0]) {
-                plain[i] = 1;
-            //対象とするbitが1で投票結果が0の方が多いなら0に反転
-            } else if plain[i] == 1 && (votes[i][0] > votes[i][1]) {
-                plain[i] = 0;
-            }
+    }
+
+    #[test]
+    fn generator_from_parity_check() {
+        // Gaussian elimination of the FT8 parity checks gives back the FT8 generator
+        let code = &*FTX_LDPC_CODE;
+        let h: Vec<Vec<u32>> = code
+            .nm
+            .iter()
+            .map(|row| (0..code.n).map(|i| row.contains(&i) as u32).collect())
+            .collect();
+        assert_eq!(LdpcCode::from_parity_check(&h), *code);
+
+        let tables = code.to_tables("FTX_LDPC");
+        assert!(tables.contains("pub const FTX_LDPC_NM: [[usize; 7]; 83] = [\n    [4, 31, 59, 91, 92, 96, 153],\n"));
+        assert!(tables.contains("    [\n        0x83, 0x29, 0xce, 0x11, 0xbf, 0x31, 0xea, 0xf5, 0x09, 0xf2, 0x7f, 0xc0,\n    ],\n"));
+        assert!(tables.contains("pub const FTX_LDPC_MN: [[usize; 3]; 174] = [\n    [16, 45, 73],\n"));
+
+        // Columns are reordered when the last columns are dependent, and
+        // a redundant check lowers only the rank
+        let h = vec![
+            vec![1, 1, 0, 1, 0],
+            vec![0, 1, 1, 1, 0],
+            vec![1, 0, 1, 0, 0],
+        ];
+        let code = LdpcCode::from_parity_check(&h);
+        assert_eq!((code.n, code.k, code.m()), (5, 3, 3));
+        for message in 0u8..8 {
+            let mut codeword = [0u8; 1];
+            code.encode(&[message << 5], &mut codeword);
+            let bits: Vec<u8> = (0..5).map(|i| (codeword[0] >> (7 - i)) & 1).collect();
+            assert_eq!(code.check(&bits), 0);
         }
-        //　検査行列を満たすかチェック
-        if ldpc_check(plain) == 0 {
-            return 0;
+    }
+
+    #[test]
+    fn any_code_encodes_and_checks() {
+        let code = hamming();
+        for message in 0u8..16 {
+            let mut codeword = [0u8; 1];
+            // Stray bits after the message must not leak into the parity
+            code.encode(&[(message << 4) | 0x0f], &mut codeword);
+            assert_eq!(codeword[0] >> 4, message);
+            let plain = bits(&code, &codeword);
+            assert_eq!(code.check(&plain), 0);
+            let mut wrong = plain.clone();
+            wrong[message as usize % 7] ^= 1;
+            assert!(code.check(&wrong) > 0);
+        }
+
+        // Decode through the same interface, with some bits flipped at low confidence.
+        // No bit shares a check with two flipped bits, so bit flipping corrects them too.
+        let code = &*FTX_LDPC_CODE;
+        let message: Vec<u8> = (0..code.k_bytes() as u8).map(|i| i.wrapping_mul(37)).collect();
+        let mut codeword = vec![0u8; code.n_bytes()];
+        code.encode(&message, &mut codeword);
+        let plain = bits(code, &codeword);
+        assert_eq!(code.check(&plain), 0);
+        let mut llr: Vec<f32> = plain.iter().map(|&b| if b == 1 { 3.0 } else { -3.0 }).collect();
+        let mut near = vec![false; code.n];
+        let mut flipped = 0;
+        for (checks, x) in code.mn.iter().zip(llr.iter_mut()) {
+            let neighbours: Vec<usize> = checks.iter().flat_map(|&m| code.nm[m].clone()).collect();
+            if neighbours.iter().any(|&b| near[b]) {
+                continue;
+            }
+            neighbours.iter().for_each(|&b| near[b] = true);
+            *x *= -0.3;
+            flipped += 1;
         }
+        assert!(flipped >= 3, "{}", flipped);
+        let mut decoded = vec![0u8; code.n];
+        let mut scratch = LdpcScratch::default();
+        assert_eq!(code.decode(&llr, 20, &mut decoded, &mut scratch), 0);
+        assert_eq!(decoded, plain);
+        // The scratch space left by the previous codeword does not leak into the next
+        let llr: Vec<f32> = plain.iter().map(|&b| if b == 1 { 3.0 } else { -3.0 }).collect();
+        decoded.fill(0);
+        assert_eq!(code.decode(&llr, 20, &mut decoded, &mut scratch), 0);
+        assert_eq!(decoded, plain);
     }
-    //所定の繰り返しで終わらなければエラー
-    1
 }
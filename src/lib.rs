@@ -0,0 +1,21 @@
+pub mod channel;
+pub mod constant;
+pub mod composer;
+pub mod crc;
+pub mod error;
+pub mod ft8decode;
+pub mod ft8encode;
+pub mod gfsk;
+pub mod hashtable;
+pub mod iq;
+pub mod ldpc;
+pub mod monitor;
+pub mod pack;
+pub mod peg;
+pub mod payload;
+pub mod protocol;
+pub mod scheduler;
+pub mod spectrogram;
+pub mod tanner;
+pub mod text;
+pub mod unpack;
@@ -1,63 +1,264 @@
-use monitor::Waterfall;
 use std::collections::HashMap;
 use std::env;
+use std::error::Error;
 use std::fs::File;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread;
 use std::time::Instant;
 use wav_io::header::*;
 use wav_io::*;
 
-mod constant;
-mod crc;
-mod ft8decode;
-mod ft8encode;
-mod gfsk;
-mod ldpc;
-mod monitor;
-mod pack;
-mod spectrogram;
-mod text;
-mod unpack;
-
-use crate::constant::{FT8_NN, FT8_SLOT_TIME, FT8_SYMBOL_PERIOD, FTX_LDPC_K_BYTES};
-use crate::ft8decode::*;
-use crate::ft8encode::*;
-use crate::gfsk::{synth_gfsk, FT8_SYMBOL_BT};
-use crate::monitor::Candidate;
-use crate::monitor::{Config, Monitor};
-use crate::pack::*;
+use rustft8::channel::{snr_amplitude, Channel};
+use rustft8::constant::FTX_LDPC_K_BYTES;
+use rustft8::ft8decode::*;
+use rustft8::error::Ft8Error;
+use rustft8::ft8encode::*;
+use rustft8::gfsk::{gfsk_pulse, synth_gfsk, synth_gfsk_iq};
+use rustft8::hashtable::CallsignHashTable;
+use rustft8::iq::{write_cf32, write_iq_wav};
+use rustft8::monitor::{Candidate, Waterfall};
+use rustft8::monitor::{Config, MagFormat, Monitor, StreamMonitor, Window};
+use rustft8::pack::*;
+use rustft8::protocol::{FT4, FT8};
+use rustft8::spectrogram::plot_graph;
+use rustfft::num_complex::Complex;
+
+// Default sample rate of the --iq output
+const IQ_SAMPLE_RATE: u32 = 48000;
 
 fn get_df(c: &Candidate, wf: &Waterfall) -> (f32, f32) {
+    let symbol_period = wf.protocol.symbol_period;
     let freq_hz =
-        (c.freq_offset as f32 + c.freq_sub as f32 / wf.freq_osr as f32) / FT8_SYMBOL_PERIOD as f32;
+        (c.freq_offset as f32 + c.freq_sub as f32 / wf.freq_osr as f32) / symbol_period;
     let time_sec =
-        (c.time_offset as f32 + c.time_sub as f32 / wf.time_osr as f32) * FT8_SYMBOL_PERIOD as f32;
+        (c.time_offset as f32 + c.time_sub as f32 / wf.time_osr as f32) * symbol_period;
     
         (freq_hz, time_sec)
 }
 
+// Callsigns seen in previous runs, used to resolve hashed callsigns.
+// Kept in $XDG_DATA_HOME/rustft8 (~/.local/share/rustft8) unless --hash-table is given.
+fn default_hash_table_path() -> PathBuf {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    match data_dir {
+        Some(dir) => dir.join("rustft8").join("callsign_hash.txt"),
+        None => PathBuf::from("callsign_hash.txt"),
+    }
+}
+
+// rect, hann, hamming, blackman, blackman-harris, kaiser:<beta> or custom:<file>
+// (whitespace separated coefficients)
+fn parse_window(arg: &str) -> Result<Window, Box<dyn Error>> {
+    let window = match arg.split_once(':') {
+        Some(("kaiser", beta)) => Window::Kaiser(beta.parse()?),
+        Some(("custom", path)) => Window::custom(
+            std::fs::read_to_string(path)?
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()?,
+        )?,
+        _ => match arg {
+            "rect" => Window::Rect,
+            "hann" => Window::Hann,
+            "hamming" => Window::Hamming,
+            "blackman" => Window::Blackman,
+            "blackman-harris" => Window::BlackmanHarris,
+            _ => return Err(format!("unknown window '{}'", arg).into()),
+        },
+    };
+    Ok(window)
+}
+
+// Decode the slot as it arrives, printing the new messages of each pass
+fn run_stream(
+    config: &Config,
+    samples: &[f32],
+    hash_table: &CallsignHashTable,
+    hash_table_path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut mon = StreamMonitor::new(config);
+    let mut decoder = IncrementalDecoder::new(config.protocol.decode_passes);
+    let print = |mon: &StreamMonitor, messages: Vec<Message>| {
+        let t = mon.wf.num_blocks as f32 * config.protocol.symbol_period;
+        for mesg in messages {
+            let (score, dt, df) = mesg.df[0];
+            println!(
+                "{:5.2}s : {}Hz {}s {}dB S={}: {}",
+                t,
+                (df * 10.0).round() / 10.0,
+                (dt * 10.0).round() / 10.0,
+                mesg.snr.round(),
+                score,
+                mesg.text
+            );
+        }
+    };
+    for chunk in samples.chunks(480) {
+        if mon.push(chunk) > 0 {
+            let messages = decoder.poll(
+                &mon.wf,
+                config.sync_min_score,
+                config.ldpc_max_iteration,
+                hash_table,
+            );
+            print(&mon, messages);
+        }
+    }
+    let messages = decoder.finish(
+        &mon.wf,
+        config.sync_min_score,
+        config.ldpc_max_iteration,
+        hash_table,
+    );
+    print(&mon, messages);
+    hash_table.save(hash_table_path)?;
+    Ok(())
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    if let Err(e) = run() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args: Vec<String> = env::args().collect();
+
+    // --ft4 selects FT4, otherwise FT8
+    let protocol = match args.iter().position(|arg| arg == "--ft4") {
+        Some(pos) => {
+            args.remove(pos);
+            &FT4
+        }
+        None => &FT8,
+    };
+
+    // --snr treats <attn> as SNR in 2500 Hz and adds white noise
+    let with_noise = match args.iter().position(|arg| arg == "--snr") {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    };
+
+    // --plot writes the GFSK pulse and the first samples of the signal as PNG
+    let plot = match args.iter().position(|arg| arg == "--plot") {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    };
+
+    // --iq <file> also writes complex baseband: raw cf32 for *.cf32, otherwise I/Q WAV
+    let iq_path = match args.iter().position(|arg| arg == "--iq") {
+        Some(pos) => {
+            args.remove(pos);
+            if pos == args.len() {
+                return Err("--iq needs a file name".into());
+            }
+            Some(args.remove(pos))
+        }
+        None => None,
+    };
+
+    // --iq-rate <Hz> sets the sample rate of the --iq output (default 48000)
+    let iq_rate = match args.iter().position(|arg| arg == "--iq-rate") {
+        Some(pos) => {
+            args.remove(pos);
+            if pos == args.len() {
+                return Err("--iq-rate needs a sample rate".into());
+            }
+            match args.remove(pos).parse::<u32>()? {
+                0 => return Err("--iq-rate must be positive".into()),
+                rate => rate,
+            }
+        }
+        None => IQ_SAMPLE_RATE,
+    };
+
+    // --iq-offset <Hz> moves the centre of the tones away from 0 Hz in the --iq output
+    let iq_offset = match args.iter().position(|arg| arg == "--iq-offset") {
+        Some(pos) => {
+            args.remove(pos);
+            if pos == args.len() {
+                return Err("--iq-offset needs a frequency".into());
+            }
+            args.remove(pos).parse::<f32>()?
+        }
+        None => 0.0,
+    };
+
+    // --stream feeds the samples in 40 ms chunks and decodes at the protocol's decode passes
+    let stream = match args.iter().position(|arg| arg == "--stream") {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    };
+
+    // --window <name> selects the FFT window (default blackman)
+    let window = match args.iter().position(|arg| arg == "--window") {
+        Some(pos) => {
+            args.remove(pos);
+            if pos == args.len() {
+                return Err("--window needs a window name".into());
+            }
+            parse_window(&args.remove(pos))?
+        }
+        None => Window::default(),
+    };
+
+    // --hash-table <file> overrides where the callsign hash table is kept
+    let hash_table_path = match args.iter().position(|arg| arg == "--hash-table") {
+        Some(pos) => {
+            args.remove(pos);
+            if pos == args.len() {
+                return Err("--hash-table needs a file name".into());
+            }
+            PathBuf::from(args.remove(pos))
+        }
+        None => default_hash_table_path(),
+    };
+
+    // --f32 keeps the waterfall in f32 instead of 8 bits
+    let mag_format = match args.iter().position(|arg| arg == "--f32") {
+        Some(pos) => {
+            args.remove(pos);
+            MagFormat::F32
+        }
+        None => MagFormat::U8,
+    };
 
     let config = Config {
+        protocol,
         sample_rate: 12000,
         time_osr: 4,
         freq_osr: 2,
         sync_min_score: 10,
         num_threads: 8,
         ldpc_max_iteration: 20,
+        window,
+        mag_format,
     };
 
-    let mut samples= Vec::new();
+    let mut samples: Vec<f32>;
     let mut header = WavHeader::new_mono();
     let mut packed = [0u8; FTX_LDPC_K_BYTES];
-    let mut tones = [0usize; FT8_NN];
+    let hash_table = CallsignHashTable::load(&hash_table_path)?;
 
     if args.len() == 2 {
         // Input from file
-        let input_wav = File::open(&args[1]).unwrap();
-        (header, samples) = read_from_file(input_wav).unwrap();
+        let input_wav = File::open(&args[1])?;
+        (header, samples) = read_from_file(input_wav).map_err(Ft8Error::from)?;
 
         if header.channels >= 2 {
             samples = utils::stereo_to_mono(samples);
@@ -69,20 +270,24 @@ fn main() {
             header.sample_rate = config.sample_rate;
         }
 
-        let mut file_out = File::create("./resampled.wav").unwrap();
-        writer::to_file(&mut file_out, &WavData::new(header, samples.clone())).unwrap();
+        let mut file_out = File::create("./resampled.wav")?;
+        writer::to_file(&mut file_out, &WavData::new(header, samples.clone()))
+            .map_err(Ft8Error::from)?;
     } else if args.len() > 2 {
-        // Generate FT8 symbols and GFSK modulated samples.
-        let frequency = args[1].parse::<f32>().unwrap();
-		let attn = args[2].parse::<f32>().unwrap();
-		let attn = 10.0_f32.powf(attn/20.0);
+        // Generate FT8/FT4 symbols and GFSK modulated samples.
+        let frequency = args[1].parse::<f32>()?;
+        let attn = args[2].parse::<f32>()?;
+        let attn = if with_noise {
+            snr_amplitude(attn, config.sample_rate)
+        } else {
+            10.0_f32.powf(attn / 20.0)
+        };
 
-        if pack77(&args[3], &mut packed) < 0 {
-            println!("Cannot parse message! {}", &args[1]);
-            return;
-        }
+        pack77(&args[3], &hash_table, &mut packed)?;
 
-        ft8_encode(&packed, &mut tones);
+        let mut tones = vec![0usize; config.protocol.num_symbols];
+        ftx_encode(config.protocol, &packed, &mut tones);
+        let symbol_period = config.protocol.symbol_period;
 
         print!("FSK tones: ");
         for t in tones.iter() {
@@ -91,21 +296,60 @@ fn main() {
         println!();
 
         let num_samples =
-            (0.5 + FT8_NN as f32 * FT8_SYMBOL_PERIOD * config.sample_rate as f32) as usize;
-        let num_silence = ((FT8_SLOT_TIME * config.sample_rate as f32) as usize - num_samples) / 2;
+            (0.5 + tones.len() as f32 * symbol_period * config.sample_rate as f32) as usize;
+        let num_silence = ((config.protocol.slot_time * config.sample_rate as f32) as usize
+            - num_samples)
+            / 2;
 
         samples = vec![0.0; num_samples];
 
         synth_gfsk(
             &tones,
-            FT8_NN,
+            tones.len(),
             frequency,
-            FT8_SYMBOL_BT,
-            FT8_SYMBOL_PERIOD,
+            config.protocol.symbol_bt,
+            symbol_period,
             config.sample_rate as f32,
             &mut samples,
         );
 
+        if let Some(path) = &iq_path {
+            // The tones are centred on iq_offset
+            let f0 = iq_offset - (config.protocol.num_tones - 1) as f32 / 2.0 / symbol_period;
+            let n_iq = (0.5 + iq_rate as f32 * symbol_period) as usize * tones.len();
+            let mut iq = vec![Complex::new(0.0, 0.0); n_iq];
+            synth_gfsk_iq(
+                &tones,
+                tones.len(),
+                f0,
+                config.protocol.symbol_bt,
+                symbol_period,
+                iq_rate as f32,
+                &mut iq,
+            );
+            if path.ends_with(".cf32") {
+                write_cf32(&mut File::create(path)?, &iq)?;
+            } else {
+                write_iq_wav(path, iq_rate, &iq)?;
+            }
+        }
+
+        if plot {
+            let n_spsym = (0.5 + config.sample_rate as f32 * symbol_period) as usize;
+            let mut pulse = vec![0.0; 3 * n_spsym];
+            gfsk_pulse(n_spsym, config.protocol.symbol_bt, &mut pulse);
+            plot_graph(
+                "./gauss-envelope.png",
+                "GFSK Phase Envelope",
+                &pulse,
+                0,
+                pulse.len(),
+                0.0,
+                1.0,
+            );
+            plot_graph("./signal.png", "GFSK Signal", &samples, 0, 500, -1.5, 1.5);
+        }
+
         let mut silence_before = vec![0.0; num_silence];
         let mut silence_after = vec![0.0; num_silence];
 
@@ -114,17 +358,25 @@ fn main() {
         samples = silence_before;
 
         samples = samples.iter().map(|x| * x * attn).collect::<Vec<_>>();
+        if with_noise {
+            samples = Channel::awgn(config.sample_rate).apply(&samples);
+        }
 
         header.sample_rate = config.sample_rate;
         header.channels = 1;
         header.bits_per_sample = 32;
         header.sample_format = SampleFormat::Float;
 
-        let mut file_out = File::create("./resampled.wav").unwrap();
-        writer::to_file(&mut file_out, &WavData::new(header, samples.clone())).unwrap();
+        let mut file_out = File::create("./resampled.wav")?;
+        writer::to_file(&mut file_out, &WavData::new(header, samples.clone()))
+            .map_err(Ft8Error::from)?;
     } else {
-        print!("Usage: rustft8 <wavfile> | <freq> <message> ");
-        return;
+        println!("Usage: rustft8 [--ft4] [--hash-table <file>] [--stream] [--window <name>] [--f32] <wavfile> | [--snr] [--iq <file>] [--iq-rate <Hz>] [--iq-offset <Hz>] [--plot] <freq> <attn> <message>");
+        return Ok(());
+    }
+
+    if stream {
+        return run_stream(&config, &samples, &hash_table, &hash_table_path);
     }
 
     print!(
@@ -150,6 +402,7 @@ fn main() {
     let sched = mon.decode_frequencies(config.num_threads);
     let wf = Arc::new(mon.wf);
     let config = Arc::new(config);
+    let hash_table = Arc::new(hash_table);
     let mut handles = vec![];
     let message_hash: Arc<Mutex<HashMap<u16, Message>>> = Arc::new(Mutex::new(HashMap::new()));
 
@@ -158,18 +411,23 @@ fn main() {
         let wf = Arc::clone(&wf);
         let config = Arc::clone(&config);
         let message_hash = Arc::clone(&message_hash);
+        let hash_table = Arc::clone(&hash_table);
         let handle = thread::spawn(move || {
             let mut find_sync: FT8FindSync = FT8FindSync::new(&wf);
             let mut candidates: Vec<Candidate> = Vec::new();
             let _num =
                 find_sync.ft8_find_sync(freq_from, freq_to, config.sync_min_score, &mut candidates);
-            let decode = FT8Decode::new(&wf);
+            let decode = FT8Decode::new(&wf, &hash_table);
             let mut success = 0;
             for c in candidates.iter() {
                 let mut message = Message::new();
-                if decode.ft8_decode(c, config.ldpc_max_iteration, &mut message) {
+                if decode
+                    .ft8_decode(c, config.ldpc_max_iteration, &mut message)
+                    .is_ok()
+                {
                     let (freq_hz, time_sec)  = get_df(c, &wf);
-                    let mut message_hash = message_hash.lock().unwrap();
+                    let mut message_hash =
+                        message_hash.lock().unwrap_or_else(PoisonError::into_inner);
                     success += 1;
                     match message_hash.get_mut(&message.hash) {
                         None => {
@@ -194,10 +452,14 @@ fn main() {
     }
 
     for handle in handles {
-        handle.join().unwrap()
+        handle
+            .join()
+            .map_err(|_| "decoder thread panicked")?;
     }
 
-    let mut messages = message_hash.lock().unwrap();
+    hash_table.save(&hash_table_path)?;
+
+    let mut messages = message_hash.lock().unwrap_or_else(PoisonError::into_inner);
     println!(
         "Decoded messages: {} stations. ({:?} elapsed.)",
         messages.len(),
@@ -206,12 +468,15 @@ fn main() {
     for (i, mesg) in messages.values_mut().enumerate() {
         let (score, df, dt) = mesg.df[0];
         println!(
-            "{} : {}Hz {}s S={}: {}",
+            "{} : {}Hz {}s {}dB S={}: {}",
             i + 1,
             (dt * 10.0).round() / 10.0,
             (df * 10.0).round() / 10.0,
+            mesg.snr.round(),
             score,
             mesg.text
         );
     }
+
+    Ok(())
 }
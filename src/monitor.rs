@@ -1,16 +1,219 @@
-use crate::constant::{FT8_SLOT_TIME, FT8_SYMBOL_PERIOD};
+use crate::error::Ft8Error;
+use crate::protocol::Protocol;
 use crate::spectrogram::*;
 use realfft::{RealFftPlanner, RealToComplex};
 use rustfft::num_complex::Complex;
 use std::sync::Arc;
 
 pub struct Config {
+    pub protocol: &'static Protocol, // FT8 or FT4
     pub sample_rate: u32, /* Wave sample rate */
     pub time_osr: usize,
     pub freq_osr: usize,
     pub sync_min_score: i32,
     pub num_threads: usize,
     pub ldpc_max_iteration: i32,
+    pub window: Window, // FFT window of the STFT
+    pub mag_format: MagFormat, // Storage of the waterfall
+}
+
+#[cfg(test)]
+impl Config {
+    // テスト用の設定 (12 kHz, 2x2オーバーサンプリング, 1スレッド)
+    pub fn default_for(protocol: &'static Protocol) -> Self {
+        Config {
+            protocol,
+            sample_rate: 12000,
+            time_osr: 2,
+            freq_osr: 2,
+            sync_min_score: 10,
+            num_threads: 1,
+            ldpc_max_iteration: 20,
+            window: Window::Blackman,
+            mag_format: MagFormat::U8,
+        }
+    }
+}
+
+// Bins on each side used for the noise floor of a bin (100 Hz for FT8)
+const NOISE_SPAN: usize = 16;
+
+// Level of the noise floor in the f32 waterfall (0.5 dB units)
+const F32_NOISE_LEVEL: f32 = 80.0;
+// Blocks at the start of the slot whose median sets the f32 reference level
+const F32_REFERENCE_BLOCKS: usize = 8;
+
+// ウォーターフォールの格納形式
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MagFormat {
+    // 2 * dB + 240 clipped to 0..255
+    #[default]
+    U8,
+    // 2 * dB without quantisation or clipping, relative to the noise floor of the slot
+    F32,
+}
+
+// Magnitudes in 0.5 dB units, either representation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Magnitudes {
+    U8(Vec<u8>),
+    F32(Vec<f32>),
+}
+
+// Element type of Magnitudes. Inner loops take &[impl MagValue] and match on
+// the representation once per slice instead of on every access.
+pub trait MagValue: Copy {
+    fn to_f32(self) -> f32;
+}
+
+impl MagValue for u8 {
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+}
+
+impl MagValue for f32 {
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+impl Magnitudes {
+    fn new(format: MagFormat, size: usize) -> Self {
+        match format {
+            MagFormat::U8 => Magnitudes::U8(vec![0; size]),
+            MagFormat::F32 => Magnitudes::F32(vec![0.0; size]),
+        }
+    }
+
+    pub fn format(&self) -> MagFormat {
+        match self {
+            Magnitudes::U8(_) => MagFormat::U8,
+            Magnitudes::F32(_) => MagFormat::F32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Magnitudes::U8(m) => m.len(),
+            Magnitudes::F32(m) => m.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> f32 {
+        match self {
+            Magnitudes::U8(m) => m[i] as f32,
+            Magnitudes::F32(m) => m[i],
+        }
+    }
+
+    // 8-bit value for display
+    pub fn get_u8(&self, i: usize) -> u8 {
+        match self {
+            Magnitudes::U8(m) => m[i],
+            Magnitudes::F32(m) => m[i].clamp(0.0, 255.0) as u8,
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Magnitudes::U8(m) => m.fill(0),
+            Magnitudes::F32(m) => m.fill(0.0),
+        }
+    }
+}
+
+// FFT窓関数
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Window {
+    Rect,
+    Hann,
+    Hamming,
+    #[default]
+    Blackman,
+    BlackmanHarris, // 4-term, -92 dB sidelobes
+    Kaiser(f32),    // beta
+    Custom(Vec<f32>), // Coefficients, resampled to the FFT size
+}
+
+impl Window {
+    // Custom window from its coefficients. The noise bandwidth and the SNR divide by
+    // the sum of the coefficients, so it has to be positive.
+    pub fn custom(coefficients: Vec<f32>) -> Result<Window, Ft8Error> {
+        let sum: f32 = coefficients.iter().sum();
+        if !(sum > 0.0 && sum.is_finite()) {
+            return Err(Ft8Error::InvalidWindow { sum });
+        }
+        Ok(Window::Custom(coefficients))
+    }
+
+    // n coefficients of the periodic window
+    pub fn coefficients(&self, n: usize) -> Vec<f32> {
+        let pi2 = 2.0 * std::f32::consts::PI;
+        // a0 - a1 cos(2πx) + a2 cos(4πx) - a3 cos(6πx)
+        let cosine = |a: &[f32]| -> Vec<f32> {
+            (0..n)
+                .map(|i| {
+                    let x = pi2 * i as f32 / n as f32;
+                    let mut sign = 1.0;
+                    let mut w = 0.0;
+                    for (k, ak) in a.iter().enumerate() {
+                        w += sign * ak * (k as f32 * x).cos();
+                        sign = -sign;
+                    }
+                    w
+                })
+                .collect()
+        };
+        match self {
+            Window::Rect => vec![1.0; n],
+            Window::Hann => cosine(&[0.5, 0.5]),
+            Window::Hamming => cosine(&[0.54, 0.46]),
+            Window::Blackman => cosine(&[0.42, 0.5, 0.08]),
+            Window::BlackmanHarris => cosine(&[0.35875, 0.48829, 0.14128, 0.01168]),
+            Window::Kaiser(beta) => (0..n)
+                .map(|i| {
+                    let r = 2.0 * i as f32 / n as f32 - 1.0;
+                    bessel_i0(beta * (1.0 - r * r).sqrt()) / bessel_i0(*beta)
+                })
+                .collect(),
+            Window::Custom(c) if c.len() == n => c.clone(),
+            //長さが違う場合は線形補間する
+            Window::Custom(c) => (0..n)
+                .map(|i| {
+                    if c.len() < 2 {
+                        return c.first().copied().unwrap_or(1.0);
+                    }
+                    let x = i as f32 * (c.len() - 1) as f32 / (n - 1).max(1) as f32;
+                    let j = (x as usize).min(c.len() - 2);
+                    let frac = x - j as f32;
+                    c[j] * (1.0 - frac) + c[j + 1] * frac
+                })
+                .collect(),
+        }
+    }
+}
+
+// Modified Bessel function of the first kind, order 0 (power series)
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0f32;
+    let mut term = 1.0f32;
+    let q = x * x / 4.0;
+    for k in 1..50 {
+        term *= q / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+    sum
 }
 #[derive(Debug)]
 pub struct Candidate {
@@ -27,27 +230,73 @@ pub struct Waterfall {
     pub num_bins: usize,   // number of FFT bins in terms of 6.25 Hz
     pub time_osr: usize,   // number of time subdivisions
     pub freq_osr: usize,   // number of frequency subdivisions
-    pub mag: Vec<u8>,      //<FFT magnitudes stored as uint8_t[blocks][time_osr][freq_osr][num_bins]
+    pub mag: Magnitudes,   //<FFT magnitudes stored as [blocks][time_osr][freq_osr][num_bins]
+    pub reference: f32,    // 2 * dB of the f32 level 0, set from the first blocks of the slot
+    pub noise_bandwidth: f32, // Equivalent noise bandwidth of a bin (Hz)
+    pub symbol_gain: f32,  // Power captured from one symbol (the FFT spans two)
     pub block_stride: usize, //< Helper value = time_osr * freq_osr * num_bins
+    pub protocol: &'static Protocol, // Mode of the signals in the waterfall
 }
 
 impl Waterfall {
-    pub fn new(max_blocks: usize, num_bins: usize, time_osr: usize, freq_osr: usize) -> Self {
+    pub fn new(
+        max_blocks: usize,
+        num_bins: usize,
+        time_osr: usize,
+        freq_osr: usize,
+        protocol: &'static Protocol,
+        format: MagFormat,
+    ) -> Self {
         let mag_size = max_blocks * time_osr * freq_osr * num_bins;
         let block_stride = time_osr * freq_osr * num_bins;
-        let mut mag = Vec::with_capacity(mag_size);
-        for _i in 0..mag_size {
-            mag.push(0);
-        }
         Waterfall {
             max_blocks,
             num_blocks: 0,
             num_bins,
             time_osr,
             freq_osr,
-            mag,
+            mag: Magnitudes::new(format, mag_size),
+            reference: 0.0,
+            noise_bandwidth: 1.0 / protocol.symbol_period,
+            symbol_gain: 1.0,
             block_stride,
+            protocol,
+        }
+    }
+
+    // Mean power of every bin over the slot, indexed [freq_sub][bin]
+    pub fn average_spectrum(&self) -> Vec<f32> {
+        let num_rows = self.num_blocks * self.time_osr;
+        let mut average = vec![0.0f32; self.freq_osr * self.num_bins];
+        for row in 0..num_rows {
+            let offset = row * self.freq_osr * self.num_bins;
+            for (i, v) in average.iter_mut().enumerate() {
+                //0.5dB単位の値を電力に戻す
+                *v += 10f32.powf(self.mag.get(offset + i) / 20.0);
+            }
+        }
+        for v in average.iter_mut() {
+            *v /= num_rows.max(1) as f32;
         }
+        average
+    }
+
+    // ビン毎のノイズフロア(電力)
+    // 平均スペクトルの前後NOISE_SPANビンの中でpercentile(0..1)番目の値。
+    // 局の間の隙間を拾うので、混雑したバンドでも局の信号に影響されにくい
+    pub fn noise_floor(&self, percentile: f32) -> Vec<f32> {
+        let average = self.average_spectrum();
+        let mut floor = Vec::with_capacity(average.len());
+        for spectrum in average.chunks(self.num_bins) {
+            for bin in 0..self.num_bins {
+                let from = bin.saturating_sub(NOISE_SPAN);
+                let to = (bin + NOISE_SPAN + 1).min(self.num_bins);
+                let mut window = spectrum[from..to].to_vec();
+                let k = ((window.len() - 1) as f32 * percentile.clamp(0.0, 1.0)).round() as usize;
+                floor.push(*window.select_nth_unstable_by(k, f32::total_cmp).1);
+            }
+        }
+        floor
     }
 
     pub fn get_index(&self, candidate: &Candidate) -> i32 {
@@ -59,149 +308,188 @@ impl Waterfall {
     }
 }
 
-pub struct Monitor<'a> {
-    pub block_size: usize, // Number of samples per symbol (block)
-    subblock_size: usize,  // Analysis shift size (number of samples)
-    nfft: usize,           // FFT size
+// STFT shared by Monitor and StreamMonitor
+struct Stft {
+    block_size: usize,    // Number of samples per symbol (block)
+    subblock_size: usize, // Analysis shift size (number of samples)
+    nfft: usize,          // FFT size
     fft_forward: Arc<dyn RealToComplex<f32>>, // FFT forward
-    samples: &'a Vec<f32>, // Sampling data
-    window: Vec<f32>,      // Window function
+    window: Vec<f32>,     // Window function
     spectrum: Vec<Complex<f32>>, // FFT bin
-    pub wf: Waterfall,     // Waterfall object
-    pub max_mag: f32,      // Maximum detected magnitude (debug stats)
-}
-
-// FFT窓関数：矩形窓
-#[cfg(feature = "window_rect")]
-fn wfunc(i: usize, n: usize) -> f32 {
-    1.0
+    indata: Vec<f32>,     // FFT input
 }
 
-// FFT窓関数：ハン窓
-#[cfg(feature = "window_hann")]
-fn wfunc(i: usize, n: usize) -> f32 {
-    (std::f32::consts::PI * i as f32 / n as f32).sin().powi(2)
-}
-
-// FFT窓関数：ハミング窓
-#[cfg(feature = "window_hamming")]
-fn wfunc(i: usize, n: usize) -> f32 {
-    let a0 = 0.54;
-    let a1 = 0.46;
-    let pi2 = 2.0 * std::f32::consts::PI;
-    
-    a0 - a1 * (pi2 * i as f32 / n as f32).cos()
-}
-
-// FFT窓関数：ブラックマン窓
-#[cfg(feature = "window_blackman")]
-fn wfunc(i: usize, n: usize) -> f32 {
-    let a0 = 0.42;
-    let a1 = 0.5;
-    let a2 = 0.08;
-    let pi2 = 2.0 * std::f32::consts::PI;
-    let x = i as f32 / n as f32;
-    
-    a0 - a1 * (pi2 * x).cos() + a2 * (2.0 * pi2 * x).cos()
+pub struct Monitor<'a> {
+    pub block_size: usize, // Number of samples per symbol (block)
+    stft: Stft,
+    samples: &'a [f32],    // Sampling data
+    pub wf: Waterfall,     // Waterfall object
+    pub max_mag: f32,      // Maximum detected magnitude (debug stats)
 }
 
-impl<'a> Monitor<'a> {
-    pub fn new(config: &Config, samples: &'a Vec<f32>) -> Self {
-        let block_size = (config.sample_rate as f32 * FT8_SYMBOL_PERIOD) as usize; /* 1920 */
+impl Stft {
+    fn new(config: &Config) -> Self {
+        let symbol_period = config.protocol.symbol_period;
+        let block_size = (config.sample_rate as f32 * symbol_period) as usize; /* 1920 */
         let subblock_size = block_size / config.time_osr; /* 960 */
         let mut fft = RealFftPlanner::<f32>::new();
         let nfft = block_size * config.freq_osr; /* 3840 */
         let fft_forward = fft.plan_fft_forward(nfft);
         let fft_norm = 2.0f32 / nfft as f32;
-        let max_blocks = (FT8_SLOT_TIME / FT8_SYMBOL_PERIOD) as usize; /* 93 */
-        let num_bins = (config.sample_rate as f32 * FT8_SYMBOL_PERIOD / 2.0) as usize; /* 960 */
-        let wf = Waterfall::new(max_blocks, num_bins, config.time_osr, config.freq_osr);
-        let mut window = Vec::new();
+        let window: Vec<f32> = config
+            .window
+            .coefficients(nfft)
+            .iter()
+            .map(|w| fft_norm * w)
+            .collect();
         let mut spectrum = Vec::new();
 
-        for i in 0..nfft {
-            window.push(fft_norm * wfunc(i, nfft));
-        }
-
         for _i in 0..(nfft / 2 + 1) {
             spectrum.push(Complex::new(0.0f32, 0.0f32))
         }
-        println!(
-            "block size ={}, subblock_size = {}, num of fft = {}, max_block = {}, num of bin = {}",
-            block_size, subblock_size, nfft, max_blocks, num_bins
-        );
-        Monitor {
+        Stft {
             block_size,
             subblock_size,
             nfft,
             fft_forward,
-            samples,
             window,
             spectrum,
-            wf,
-            max_mag: -120.0f32,
+            indata: vec![0.0f32; nfft],
         }
     }
 
-    fn process(&mut self, frame: usize) {
-        if self.wf.num_blocks >= self.wf.max_blocks {
+    // ブロックの先頭から必要なサンプル数
+    fn span(&self, time_osr: usize) -> usize {
+        (time_osr - 1) * self.subblock_size + self.nfft
+    }
+
+    // Add one block to the waterfall. sample(i) is the i-th sample from the start of the block,
+    // for i < span().
+    fn process(&mut self, wf: &mut Waterfall, max_mag: &mut f32, sample: impl Fn(usize) -> f32) {
+        if wf.num_blocks >= wf.max_blocks {
             return;
         }
         //現在のブロックのバッファのオフセット値を求める
-        let mut offset = self.wf.num_blocks * self.wf.block_stride;
+        let mut offset = wf.num_blocks * wf.block_stride;
 
         //時間方向のオーバーサンプル単位でサンプル列を切り出す
-        for time_sub in 0..self.wf.time_osr {
-            //STFTの対象となるサンプル列のはじまりと終わりを求める
+        for time_sub in 0..wf.time_osr {
+            //STFTの対象となるサンプル列のはじまりを求める
             //ここでsubblock_sizeはシンボルピリオドを時間方向のオーバサンプルで割ったサイズ
-            let frame_from = frame + time_sub as usize * self.subblock_size;
-            let frame_to = frame_from + self.nfft;
+            let frame_from = time_sub * self.subblock_size;
 
-            if frame_to > self.samples.len() {
-                return;
-            }
             //サンプル列からFFTの対象となるnfft点分を部分を取り出し窓関数をかける
             //周波数方向も2倍オーバサンプルしているのでnfft=3840
-            let mut indata = self.samples[frame_from..frame_to].to_vec();
-            for (i, v) in indata.iter_mut().enumerate() {
-                *v *= self.window[i];
+            for (i, v) in self.indata.iter_mut().enumerate() {
+                *v = sample(frame_from + i) * self.window[i];
             }
 
             // 実数FFTを実行
             self.fft_forward
-                .process(&mut indata, &mut self.spectrum)
+                .process(&mut self.indata, &mut self.spectrum)
                 .unwrap();
             // FFTの結果は outputに複素数として得られる。サイズはエイリアス分を除いた nfft / 2 + 1個
             // ここで各bin[n]の周波数 f(n) = (fs / nfft) * n = (12000 / 3480) * n = 3.125 * n (Hz)
             // 周波数方向のオーバーサンプル単位にパワースペクトラムを求める
-            for freq_sub in 0..self.wf.freq_osr {
-                for bin in 0..self.wf.num_bins {
+            for freq_sub in 0..wf.freq_osr {
+                for bin in 0..wf.num_bins {
                     //一つおきにbinを求めるので6.25Hz単位
-                    let src_bin = bin * self.wf.freq_osr as usize + freq_sub as usize;
+                    let src_bin = bin * wf.freq_osr + freq_sub;
                     //binのパワーを求め
                     let mag2 = self.spectrum[src_bin].im * self.spectrum[src_bin].im
                         + self.spectrum[src_bin].re * self.spectrum[src_bin].re;
-                    //デシベルに変換し8bitにスケーリングする
+                    //デシベルに変換する
                     let db = 10.0 * (1e-12 + mag2).log10();
-                    let scaled = (2.0 * db + 240.0) as i32;
-
-                    let mag = if scaled < 0 {
-                        0
-                    } else if scaled > 255 {
-                        255
-                    } else {
-                        scaled as u8
-                    };
-                    self.wf.mag[offset] = mag;
+                    match &mut wf.mag {
+                        //8bitにスケーリングする
+                        Magnitudes::U8(mag) => {
+                            let scaled = (2.0 * db + 240.0) as i32;
+                            mag[offset] = scaled.clamp(0, 255) as u8;
+                        }
+                        //基準レベルは後でブロック単位に引く
+                        Magnitudes::F32(mag) => mag[offset] = 2.0 * db,
+                    }
                     offset += 1;
-                    if db > self.max_mag {
-                        self.max_mag = db;
+                    if db > *max_mag {
+                        *max_mag = db;
                     }
                 }
             }
         }
+        //f32はスロット最初のF32_REFERENCE_BLOCKSブロックの中央値(ノイズフロア)を基準にする。
+        //それまでのブロックは基準が決まった時点でまとめて引く
+        if let Magnitudes::F32(mag) = &mut wf.mag {
+            let num_blocks = wf.num_blocks + 1;
+            let reference_blocks = F32_REFERENCE_BLOCKS.min(wf.max_blocks);
+            if num_blocks == reference_blocks {
+                let head = &mut mag[..num_blocks * wf.block_stride];
+                let mut sorted = head.to_vec();
+                sorted.sort_by(f32::total_cmp);
+                wf.reference = sorted[sorted.len() / 2] - F32_NOISE_LEVEL;
+                for v in head.iter_mut() {
+                    *v -= wf.reference;
+                }
+            } else if num_blocks > reference_blocks {
+                let block = &mut mag[wf.num_blocks * wf.block_stride..][..wf.block_stride];
+                for v in block.iter_mut() {
+                    *v -= wf.reference;
+                }
+            }
+        }
         //次のブロックへ
-        self.wf.num_blocks += 1;
+        wf.num_blocks += 1;
+    }
+}
+
+fn new_waterfall(config: &Config) -> Waterfall {
+    let symbol_period = config.protocol.symbol_period;
+    let max_blocks = (config.protocol.slot_time / symbol_period) as usize; /* 93 */
+    let num_bins = (config.sample_rate as f32 * symbol_period / 2.0) as usize; /* 960 */
+    let mut wf = Waterfall::new(
+        max_blocks,
+        num_bins,
+        config.time_osr,
+        config.freq_osr,
+        config.protocol,
+        config.mag_format,
+    );
+    //窓関数の等価雑音帯域幅 ENBW = fs Σw² / (Σw)²
+    let nfft = (config.sample_rate as f32 * symbol_period) as usize * config.freq_osr;
+    let window = config.window.coefficients(nfft);
+    let sum: f32 = window.iter().sum();
+    let sum2: f32 = window.iter().map(|w| w * w).sum();
+    wf.noise_bandwidth = config.sample_rate as f32 * sum2 / (sum * sum);
+    //FFT長は2シンボル分なので、1シンボルのトーンは窓の中央部分だけに入る
+    let center: f32 = window[nfft / 4..nfft * 3 / 4].iter().sum();
+    wf.symbol_gain = (center / sum).powi(2);
+    wf
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new(config: &Config, samples: &'a [f32]) -> Self {
+        let stft = Stft::new(config);
+        let wf = new_waterfall(config);
+        println!(
+            "block size ={}, subblock_size = {}, num of fft = {}, max_block = {}, num of bin = {}",
+            stft.block_size, stft.subblock_size, stft.nfft, wf.max_blocks, wf.num_bins
+        );
+        Monitor {
+            block_size: stft.block_size,
+            stft,
+            samples,
+            wf,
+            max_mag: -120.0f32,
+        }
+    }
+
+    fn process(&mut self, frame: usize) {
+        //STFTの対象となるサンプル列が揃っていなければ終了
+        if frame + self.stft.span(self.wf.time_osr) > self.samples.len() {
+            return;
+        }
+        let samples = self.samples;
+        self.stft
+            .process(&mut self.wf, &mut self.max_mag, |i| samples[frame + i]);
     }
 
     pub fn process_all(&mut self) {
@@ -211,22 +499,22 @@ impl<'a> Monitor<'a> {
         }
         println!(
             "{} points FFT invoked {} times.",
-            self.nfft,
+            self.stft.nfft,
             self.wf.num_blocks * self.wf.time_osr
         );
     }
 
     // スペクトログラムをファイルにダンプ
     pub fn dump_spectrogram(&self, path: &str) {
-        let x_axis = self.nfft / 2;
+        let x_axis = self.stft.nfft / 2;
         let y_axis = self.wf.max_blocks * self.wf.time_osr;
         let mut spectr = Vec::new();
 
         for y in 0..y_axis {
             for x in 0..x_axis / 2 {
-                spectr.push(self.wf.mag[x + y * x_axis]);
+                spectr.push(self.wf.mag.get_u8(x + y * x_axis));
                 //オーバーサンプルした分を元に戻す(freq_osr = 2の時のみ対応)
-                spectr.push(self.wf.mag[x + x_axis / 2 + y * x_axis]);
+                spectr.push(self.wf.mag.get_u8(x + x_axis / 2 + y * x_axis));
             }
         }
        
@@ -246,42 +534,44 @@ impl<'a> Monitor<'a> {
             let mut sum = 0f32;
             for f in 0..self.wf.num_bins {
                 c.freq_offset = f;
-                sum += self.wf.mag[self.wf.get_index(&c) as usize] as f32;
+                sum += self.wf.mag.get(self.wf.get_index(&c) as usize);
             }
 
-            let th = sum / self.wf.num_bins as f32 / 2.0;
+            let th = (sum / self.wf.num_bins as f32 / 2.0).trunc();
             let mut count = 0;
             for f in 0..self.wf.num_bins {
                 c.freq_offset = f;
-                if self.wf.mag[self.wf.get_index(&c) as usize] > th as u8 {
+                if self.wf.mag.get(self.wf.get_index(&c) as usize) > th {
                     count += 1;
                 }
             }
 
             let average = count / num_of_threads;
-            let (mut from, mut to) = (0, 0);
+            let mut from = 0;
             count = 0;
             for f in 0..self.wf.num_bins {
                 c.freq_offset = f;
-                if self.wf.mag[self.wf.get_index(&c) as usize] > th as u8
-                    || self.wf.mag[self.wf.get_index(&c) as usize + self.wf.block_stride] > th as u8
-                    || self.wf.mag[self.wf.get_index(&c) as usize - self.wf.block_stride] > th as u8
+                let idx = self.wf.get_index(&c) as usize;
+                if self.wf.mag.get(idx) > th
+                    || self.wf.mag.get(idx + self.wf.block_stride) > th
+                    || self.wf.mag.get(idx - self.wf.block_stride) > th
                 {
                     count += 1;
                 }
                 if count > average {
                     count = 0;
-                    to = f;
-                    sched.push((from, to));
-                    from = to;
+                    sched.push((from, f));
+                    from = f;
                 }
             }
             sched
         } else {
+            //最上位のトーンがスペクトログラムに収まる範囲で分割
+            let max_bin = self.wf.num_bins - (self.wf.protocol.num_tones - 1);
             let step = self.wf.num_bins / num_of_threads;
             for bin in (0..self.wf.num_bins).step_by(step) {
-                if bin + step > self.wf.num_bins - 7 {
-                    sched.push((bin, self.wf.num_bins - 7)) 
+                if bin + step > max_bin {
+                    sched.push((bin, max_bin))
                 } else {
                     sched.push((bin, bin + step))
                 };
@@ -290,3 +580,196 @@ impl<'a> Monitor<'a> {
         }
     }
 }
+
+// Monitor for live audio. Chunks of any size are pushed as they arrive and
+// each waterfall block is computed as soon as its samples are in the ring buffer.
+pub struct StreamMonitor {
+    stft: Stft,
+    ring: Vec<f32>,        // Last span() samples
+    num_samples: usize,    // Samples pushed since the slot start
+    next_frame: usize,     // First sample of the next block
+    pub wf: Waterfall,     // Waterfall object
+    pub max_mag: f32,      // Maximum detected magnitude (debug stats)
+}
+
+impl StreamMonitor {
+    pub fn new(config: &Config) -> Self {
+        let stft = Stft::new(config);
+        let wf = new_waterfall(config);
+        let ring = vec![0.0f32; stft.span(wf.time_osr)];
+        StreamMonitor {
+            stft,
+            ring,
+            num_samples: 0,
+            next_frame: 0,
+            wf,
+            max_mag: -120.0f32,
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.stft.block_size
+    }
+
+    // Samples pushed since the last reset
+    pub fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+
+    // Start a new slot. Call at the slot boundary.
+    pub fn reset(&mut self) {
+        self.num_samples = 0;
+        self.next_frame = 0;
+        self.wf.num_blocks = 0;
+        self.wf.mag.clear();
+        self.max_mag = -120.0f32;
+    }
+
+    // Push a chunk of samples and return the number of blocks completed.
+    // Samples after the waterfall is full are ignored until reset().
+    pub fn push(&mut self, chunk: &[f32]) -> usize {
+        let span = self.ring.len();
+        let blocks = self.wf.num_blocks;
+        for &x in chunk {
+            if self.wf.num_blocks >= self.wf.max_blocks {
+                break;
+            }
+            self.ring[self.num_samples % span] = x;
+            self.num_samples += 1;
+            //リングバッファにブロック分のサンプルが揃ったらSTFT
+            if self.num_samples == self.next_frame + span {
+                let ring = &self.ring;
+                let frame = self.next_frame;
+                self.stft
+                    .process(&mut self.wf, &mut self.max_mag, |i| ring[(frame + i) % span]);
+                self.next_frame += self.stft.block_size;
+            }
+        }
+        self.wf.num_blocks - blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composer::{SlotComposer, Transmission};
+    use crate::protocol::FT8;
+
+    #[test]
+    fn stream_matches_whole_slot() {
+        let config = Config::default_for(&FT8);
+        let mut composer = SlotComposer::new(&FT8, 12000);
+        composer.add(Transmission::new("CQ JA1ZLO PM95", 1200.0, 0.2, 0.0));
+        let samples = composer.compose().unwrap();
+        let mut mon = Monitor::new(&config, &samples);
+        mon.process_all();
+
+        let mut stream = StreamMonitor::new(&config);
+        for _ in 0..2 {
+            // Irregular chunk sizes, one larger than the ring buffer
+            let mut blocks = 0;
+            let mut pos = 0;
+            for size in [480, 7, 10000, 1919, 333].iter().cycle() {
+                if pos >= samples.len() {
+                    break;
+                }
+                let end = (pos + size).min(samples.len());
+                blocks += stream.push(&samples[pos..end]);
+                pos = end;
+            }
+            assert_eq!(blocks, mon.wf.num_blocks);
+            assert_eq!(stream.wf.num_blocks, mon.wf.num_blocks);
+            assert!(stream.wf.mag == mon.wf.mag);
+            stream.reset();
+            assert_eq!((stream.wf.num_blocks, stream.num_samples()), (0, 0));
+        }
+    }
+
+    #[test]
+    fn window_shapes() {
+        let n = 3840;
+        let pi2 = 2.0 * std::f32::consts::PI;
+        let blackman = Window::Blackman.coefficients(n);
+        for i in [0, 100, 1920, 3000] {
+            let x = i as f32 / n as f32;
+            let w = 0.42 - 0.5 * (pi2 * x).cos() + 0.08 * (2.0 * pi2 * x).cos();
+            assert!((blackman[i] - w).abs() < 1e-6);
+        }
+        // Every window peaks at 1 in the middle
+        for window in [
+            Window::Rect,
+            Window::Hann,
+            Window::Hamming,
+            Window::BlackmanHarris,
+            Window::Kaiser(8.6),
+        ] {
+            let w = window.coefficients(n);
+            assert!((w[n / 2] - 1.0).abs() < 1e-5, "{:?}", window);
+            assert!(w.iter().all(|v| *v <= 1.0 + 1e-5));
+        }
+        // Kaiser with beta = 0 is rectangular
+        assert!(Window::Kaiser(0.0).coefficients(8).iter().all(|v| *v == 1.0));
+        assert!(Window::BlackmanHarris.coefficients(n)[0] < 1e-4);
+
+        // Custom coefficients are interpolated to the FFT size
+        let custom = Window::Custom(vec![0.0, 1.0, 0.0]).coefficients(5);
+        assert_eq!(custom, vec![0.0, 0.5, 1.0, 0.5, 0.0]);
+        assert_eq!(Window::Custom(vec![0.5; 4]).coefficients(4), vec![0.5; 4]);
+        // Windows that sum to zero would make the noise bandwidth NaN
+        assert!(Window::custom(vec![0.0, 1.0, 0.0]).is_ok());
+        for c in [vec![], vec![0.0; 8], vec![-1.0, 0.0, -0.5], vec![1.0, f32::NAN]] {
+            assert!(matches!(Window::custom(c), Err(Ft8Error::InvalidWindow { .. })));
+        }
+    }
+
+    #[test]
+    fn f32_waterfall_is_not_clipped() {
+        use crate::channel::Channel;
+
+        let mut composer = SlotComposer::new(&FT8, 12000);
+        composer.add(Transmission::new("CQ JA1ZLO PM95", 1200.0, 0.2, 40.0));
+        let samples = Channel::awgn(12000).apply(&composer.compose().unwrap());
+        let waterfall = |mag_format| {
+            let config = Config {
+                mag_format,
+                ..Config::default_for(&FT8)
+            };
+            let mut mon = Monitor::new(&config, &samples);
+            mon.process_all();
+            mon.wf
+        };
+        let peak_above_floor = |wf: &Waterfall| {
+            let used = wf.num_blocks * wf.block_stride;
+            let mut mag: Vec<f32> = (0..used).map(|i| wf.mag.get(i)).collect();
+            mag.sort_by(f32::total_cmp);
+            (mag[used - 1], mag[used - 1] - mag[used / 2])
+        };
+
+        let (u8_peak, u8_range) = peak_above_floor(&waterfall(MagFormat::U8));
+        let wf = waterfall(MagFormat::F32);
+        let (_, f32_range) = peak_above_floor(&wf);
+        assert_eq!(wf.mag.format(), MagFormat::F32);
+        assert_eq!(u8_peak, 255.0);
+        // 40 dB in 2500 Hz is about 66 dB in a 3.125 Hz bin
+        assert!(f32_range > u8_range + 20.0, "{} {}", f32_range, u8_range);
+        assert!(f32_range > 2.0 * 60.0);
+        // The noise floor sits at the reference level
+        assert!((wf.mag.get(wf.block_stride / 2) - F32_NOISE_LEVEL).abs() < 30.0);
+
+        // A silent first block does not pull the reference away from the noise floor
+        let mut late = samples.clone();
+        late[..2400].fill(0.0);
+        let config = Config {
+            mag_format: MagFormat::F32,
+            ..Config::default_for(&FT8)
+        };
+        let mut mon = Monitor::new(&config, &late);
+        mon.process_all();
+        let mut block: Vec<f32> = (0..mon.wf.block_stride)
+            .map(|i| mon.wf.mag.get(40 * mon.wf.block_stride + i))
+            .collect();
+        block.sort_by(f32::total_cmp);
+        let median = block[block.len() / 2];
+        assert!((median - F32_NOISE_LEVEL).abs() < 6.0, "{}", median);
+    }
+}
@@ -1,77 +1,157 @@
 use crate::constant::*;
+use crate::error::Ft8Error;
+use crate::hashtable::{ihashcall, CallsignHashTable, HashType};
+use crate::payload::Ft8Payload;
 use crate::text::*;
 
-const NTOKENS: u32 = 2063592;
-const MAX22: u32 = 4194304;
-const MAXGRID4: u16 = 32400;
-
 // TODO: This is wasteful, should figure out something more elegant
 const A0: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ+-./?";
 const A1: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const A2: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const A3: &str = "0123456789";
 const A4: &str = " ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const A5: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ/";
+
+// Store the lowest num_bits bits of value at bit position start (MSB first)
+pub fn put_bits(b77: &mut [u8; FTX_LDPC_K_BYTES], start: usize, num_bits: usize, value: u64) {
+    for i in 0..num_bits {
+        let pos = start + i;
+        let mask = 0x80u8 >> (pos % 8);
+        if (value >> (num_bits - 1 - i)) & 1 != 0 {
+            b77[pos / 8] |= mask;
+        } else {
+            b77[pos / 8] &= !mask;
+        }
+    }
+}
 
-// Pack a special token, a 22-bit hash code, or a valid base call
-// into a 28-bit integer.
-pub fn pack28(callsign: &str) -> i32 {
-    // Check for special tokens first
-    if callsign.starts_with("DE") {
-        return 0;
+// Check for a CQ modifier: 3 digits (CQ 123) or 1-4 letters (CQ DX, CQ POTA)
+pub fn is_cq_modifier(modifier: &str) -> bool {
+    let len = modifier.len();
+    (len == 3 && modifier.chars().all(|c| c.is_ascii_digit()))
+        || ((1..=4).contains(&len) && modifier.chars().all(|c| c.is_ascii_uppercase()))
+}
+
+// Return the callsign inside <...>, if bracketed
+pub fn strip_brackets(callsign: &str) -> Option<&str> {
+    callsign.strip_prefix('<')?.strip_suffix('>')
+}
+
+// Check that a token looks like a callsign, like WSJT-X chkcall:
+// up to 11 letters, digits and one '/', and the base call (the longer side of the '/')
+// has at least one letter and one digit. Rejects words such as "73", "TU" or "TEST".
+pub fn is_callsign(callsign: &str) -> bool {
+    let valid = |c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '/';
+    if callsign.len() > 11 || !callsign.chars().all(valid) {
+        return false;
     }
+    let base = match callsign.split_once('/') {
+        None => callsign,
+        Some((prefix, suffix))
+            if prefix.is_empty() || suffix.is_empty() || suffix.contains('/') =>
+        {
+            return false
+        }
+        Some((prefix, suffix)) if prefix.len() > suffix.len() => prefix,
+        Some((_, suffix)) => suffix,
+    };
+    base.len() >= 3
+        && base.chars().any(|c| c.is_ascii_uppercase())
+        && base.chars().any(|c| c.is_ascii_digit())
+}
 
-    if callsign.starts_with("QRZ") {
-        return 1;
+// Pack a special token, a 22-bit hash code, or a valid base call
+// into a 28-bit integer, and remember the callsign for later hash lookups.
+// A trailing /R or /P suffix is ignored here, it is signalled by the ipa/ipb bits.
+pub fn pack28(callsign: &str, hash_table: &CallsignHashTable) -> Result<u32, Ft8Error> {
+    let n28 = pack28_token(callsign)?;
+    if n28 >= NTOKENS {
+        let call = strip_brackets(callsign).unwrap_or(callsign);
+        hash_table.add(
+            call.strip_suffix("/R")
+                .or_else(|| call.strip_suffix("/P"))
+                .unwrap_or(call),
+        );
     }
+    Ok(n28)
+}
 
-    if callsign.starts_with("CQ") {
-        return 2;
+// Same as pack28, without touching the hash table
+pub fn pack28_token(callsign: &str) -> Result<u32, Ft8Error> {
+    let invalid = || Ft8Error::InvalidCallsign(callsign.to_string());
+
+    // Check for special tokens first
+    if callsign == "DE" {
+        return Ok(0);
     }
 
-    if callsign.starts_with("CQ_") {
-        //int nnum = 0, nlet = 0;
-        // TODO:
+    if callsign == "QRZ" {
+        return Ok(1);
     }
 
-    // TODO: Check for <...> callsign
-    /*
-    char c6[6] = { ' ', ' ', ' ', ' ', ' ', ' ' };
+    if callsign == "CQ" {
+        return Ok(2);
+    }
 
-    int length = 0; // strlen(callsign);  // We will need it later
-    while (callsign[length] != ' ' && callsign[length] != 0)
+    // CQ_nnn or CQ_aaaa (also accepted as "CQ nnn" / "CQ aaaa")
+    if let Some(modifier) = callsign
+        .strip_prefix("CQ_")
+        .or_else(|| callsign.strip_prefix("CQ "))
     {
-        length++;
+        if !is_cq_modifier(modifier) {
+            return Err(invalid());
+        }
+        if let Ok(nnn) = modifier.parse::<u32>() {
+            return Ok(3 + nnn);
+        }
+        // Letters are right-aligned in 4 characters
+        let mut m = 0u32;
+        for _ in modifier.len()..4 {
+            m *= 27;
+        }
+        for c in modifier.chars() {
+            m = m * 27 + A4.find(c).ok_or_else(invalid)? as u32;
+        }
+        return Ok(3 + 1000 + m);
+    }
+
+    // Check for <...> callsign
+    if let Some(call) = strip_brackets(callsign).filter(|call| !call.is_empty()) {
+        let n22 = ihashcall(call, HashType::Hash22).ok_or_else(invalid)?;
+        return Ok(NTOKENS + n22);
     }
 
+    let base = callsign
+        .strip_suffix("/R")
+        .or_else(|| callsign.strip_suffix("/P"))
+        .unwrap_or(callsign);
+    let length = base.len();
+
     // Copy callsign to 6 character buffer
-    if (starts_with(callsign, "3DA0") && length <= 7)
-    {
+    let c6 = if base.starts_with("3DA0") && length <= 7 {
         // Work-around for Swaziland prefix: 3DA0XYZ -> 3D0XYZ
-        memcpy(c6, "3D0", 3);
-        memcpy(c6 + 3, callsign + 4, length - 4);
-    }
-    else if (starts_with(callsign, "3X") && is_letter(callsign[2]) && length <= 7)
+        format!("3D0{:<3}", &base[4..])
+    } else if base.starts_with("3X")
+        && base.chars().nth(2).is_some_and(|c| c.is_ascii_uppercase())
+        && length <= 7
     {
         // Work-around for Guinea prefixes: 3XA0XYZ -> QA0XYZ
-        memcpy(c6, "Q", 1);
-        memcpy(c6 + 1, callsign + 2, length - 2);
-    }
-    else
-    {
-        if (is_digit(callsign[2]) && length <= 6)
-        {
-            // AB0XYZ
-            memcpy(c6, callsign, length);
-        }
-        else if (is_digit(callsign[1]) && length <= 5)
-        {
-            // A0XYZ -> " A0XYZ"
-            memcpy(c6 + 1, callsign, length);
-        }
-    }
-    */
+        format!("Q{:<5}", &base[2..])
+    } else if base.chars().nth(2).is_some_and(|c| c.is_ascii_digit()) && length <= 6 {
+        // AB0XYZ
+        format!("{:<6}", base)
+    } else if base.chars().nth(1).is_some_and(|c| c.is_ascii_digit()) && length <= 5 {
+        // A0XYZ -> " A0XYZ"
+        format!(" {:<5}", base)
+    } else {
+        return Err(invalid());
+    };
+
     // Check for standard callsign
-    let call: Vec<char> = callsign.chars().collect();
+    let call: Vec<char> = c6.chars().collect();
+    if call.len() != 6 {
+        return Err(invalid());
+    }
     if let (Some(i0), Some(i1), Some(i2), Some(i3), Some(i4), Some(i5)) = (
         A1.find(call[0]),
         A2.find(call[1]),
@@ -80,36 +160,51 @@ pub fn pack28(callsign: &str) -> i32 {
         A4.find(call[4]),
         A4.find(call[5]),
     ) {
-        let mut n28: i32 = i0 as i32;
-        n28 = n28 * 36 + i1 as i32;
-        n28 = n28 * 10 + i2 as i32;
-        n28 = n28 * 27 + i3 as i32;
-        n28 = n28 * 27 + i4 as i32;
-        n28 = n28 * 27 + i5 as i32;
-
-        return (NTOKENS + MAX22) as i32 + n28;
+        let mut n28 = i0 as u32;
+        n28 = n28 * 36 + i1 as u32;
+        n28 = n28 * 10 + i2 as u32;
+        n28 = n28 * 27 + i3 as u32;
+        n28 = n28 * 27 + i4 as u32;
+        n28 = n28 * 27 + i5 as u32;
+
+        return Ok(NTOKENS + MAX22 + n28);
     }
 
-    -1
+    Err(invalid())
+}
+
+// Pack a standard callsign without suffix, as used by the contest and
+// DXpedition messages. Tokens, hashes and compound calls are rejected.
+pub fn pack28_standard(callsign: &str) -> Result<u32, Ft8Error> {
+    match pack28_token(callsign) {
+        Ok(n28) if n28 >= NTOKENS + MAX22 && !callsign.contains('/') => Ok(n28),
+        _ => Err(Ft8Error::InvalidCallsign(callsign.to_string())),
+    }
 }
 
-pub fn packgrid(grid4: &str) -> u16 {
+// Pack a 4-character grid, a report or RRR/RR73/73 into 16 bits (ir + igrid4).
+pub fn packgrid(grid4: &str) -> Result<u16, Ft8Error> {
     // Take care of special cases
+    if grid4.trim().is_empty() {
+        return Ok(MAXGRID4 + 1);
+    }
+
     if grid4 == "RRR" {
-        return MAXGRID4 + 2;
+        return Ok(MAXGRID4 + 2);
     }
 
     if grid4 == "RR73" {
-        return MAXGRID4 + 3;
+        return Ok(MAXGRID4 + 3);
     }
 
     if grid4 == "73" {
-        return MAXGRID4 + 4;
+        return Ok(MAXGRID4 + 4);
     }
 
     let gstr: Vec<char> = grid4.chars().collect();
 
-    if in_range(gstr[0], 'A', 'R')
+    if gstr.len() == 4
+        && in_range(gstr[0], 'A', 'R')
         && in_range(gstr[1], 'A', 'R')
         && in_range(gstr[2], '0', '9')
         && in_range(gstr[3], '0', '9')
@@ -119,105 +214,163 @@ pub fn packgrid(grid4: &str) -> u16 {
         igrid4 = igrid4 * 10 + (gstr[2] as u16 - '0' as u16);
         igrid4 = igrid4 * 10 + (gstr[3] as u16 - '0' as u16);
 
-        return igrid4;
+        return Ok(igrid4);
     }
 
     // Parse report: +dd / -dd / R+dd / R-dd
-    // TODO: check the range of dd
-    if gstr[0] == 'R' {
-        let dd = dd_to_int(&grid4.chars().take(1).collect::<String>());
-        let irpt = (35 + dd) as u16;
-        (MAXGRID4 + irpt) | 0x8000 // ir = 1
-    } else {
-        let dd = dd_to_int(grid4);
-        let irpt = (35 + dd) as u16;
-        MAXGRID4 + irpt // ir = 0
+    let (ir, report) = match grid4.strip_prefix('R') {
+        Some(report) => (0x8000, report),
+        None => (0, grid4),
+    };
+    if !report.starts_with(['+', '-']) {
+        return Err(Ft8Error::GridOutOfRange(grid4.to_string()));
+    }
+    match dd_to_int(report) {
+        Some(dd) if (-50..=49).contains(&dd) => {
+            // Reports below -30 wrap around above +49
+            let dd = if dd < -30 { dd + 101 } else { dd };
+            let irpt = (35 + dd) as u16;
+            Ok((MAXGRID4 + irpt) | ir)
+        }
+        _ => Err(Ft8Error::ReportOutOfRange(grid4.to_string())),
     }
-    //return MAXGRID4 + 1;
 }
 
-// Pack Type 1 (Standard 77-bit message) and Type 2 (ditto, with a "/P" call)
-pub fn pack77_1(msg: &str, b77: &mut [u8; FTX_LDPC_K_BYTES]) -> i32 {
-    // Locate the first delimiter
-    let token: Vec<&str> = msg.split(' ').collect();
-    let n28a = pack28(token[0]);
-    let n28b = pack28(token[1]);
-
-    if n28a < 0 || n28b < 0 {
-        return -1;
+// Pack a 6-character locator such as "JO22DB" into 25 bits.
+pub fn packgrid6(grid6: &str) -> Result<u32, Ft8Error> {
+    let gstr: Vec<char> = grid6.chars().collect();
+
+    if gstr.len() != 6
+        || !in_range(gstr[0], 'A', 'R')
+        || !in_range(gstr[1], 'A', 'R')
+        || !in_range(gstr[2], '0', '9')
+        || !in_range(gstr[3], '0', '9')
+        || !in_range(gstr[4], 'A', 'X')
+        || !in_range(gstr[5], 'A', 'X')
+    {
+        return Err(Ft8Error::GridOutOfRange(grid6.to_string()));
     }
 
-    let mut igrid4 = 0u16;
+    let mut igrid6 = gstr[0] as u32 - 'A' as u32;
+    igrid6 = igrid6 * 18 + (gstr[1] as u32 - 'A' as u32);
+    igrid6 = igrid6 * 10 + (gstr[2] as u32 - '0' as u32);
+    igrid6 = igrid6 * 10 + (gstr[3] as u32 - '0' as u32);
+    igrid6 = igrid6 * 24 + (gstr[4] as u32 - 'A' as u32);
+    igrid6 = igrid6 * 24 + (gstr[5] as u32 - 'A' as u32);
 
-    if token.len() > 2 {
-        igrid4 = packgrid(token[2]);
-    } else {
-        // Two callsigns, no grid/report
-        igrid4 = packgrid(" ");
+    Ok(igrid6)
+}
+
+// Pack Type 1 (Standard 77-bit message) and Type 2 (ditto, with a "/P" call)
+pub fn pack77_1(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(Ft8Payload::parse_standard(msg)?, hash_table, b77)
+}
+
+// Pack a nonstandard callsign of up to 11 characters as a 58-bit base-38 number.
+pub fn pack58(callsign: &str) -> Result<u64, Ft8Error> {
+    let invalid = || Ft8Error::InvalidCallsign(callsign.to_string());
+    if callsign.is_empty() || callsign.len() > 11 {
+        return Err(invalid());
+    }
+    // Right-align the callsign in 11 characters
+    let mut n58 = 0u64;
+    for c in format!("{:>11}", callsign).chars() {
+        n58 = n58 * 38 + A5.find(c).ok_or_else(invalid)? as u64;
     }
+    Ok(n58)
+}
 
-    let i3 = 1u8; // No suffix or /R
+// Pack Type 4 (One nonstandard call and one hashed call)
+// e.g. "<W9XYZ> PJ4/K1ABC RR73", "PJ4/K1ABC <W9XYZ>" or "CQ PJ4/K1ABC"
+pub fn pack77_4(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(Ft8Payload::parse_nonstandard(msg)?, hash_table, b77)
+}
 
-    // TODO: check for suffixes
+// Pack Type 0.5 (telemetry): up to 18 hex digits holding a 71-bit number
+pub fn packtelemetry77(telemetry: &str, b77: &mut [u8; FTX_LDPC_K_BYTES]) -> Result<(), Ft8Error> {
+    *b77 = Ft8Payload::parse_telemetry(telemetry)?.to_bytes()?;
+    Ok(())
+}
+
+// Pack Type 0.1 (DXpedition mode), e.g. "K1ABC RR73; W9XYZ <KH1/KH7Z> -08"
+// Odd reports are rounded down to the next even value (-29 -> -30, +07 -> +06).
+pub fn packdxpedition77(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(Ft8Payload::parse_dxpedition(msg)?, hash_table, b77)
+}
 
-    // Shift in ipa and ipb bits into n28a and n28b
-    let n28a = (n28a as u32) << 1; // ipa = 0
-    let n28b = (n28b as u32) << 1; // ipb = 0
+// Pack Type 0.3 and 0.4 (ARRL Field Day), e.g. "W9XYZ K1ABC R 3A EMA"
+pub fn packfieldday77(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(Ft8Payload::parse_fieldday(msg)?, hash_table, b77)
+}
 
-    // Pack into (28 + 1) + (28 + 1) + (1 + 15) + 3 bits
-    b77[0] = (n28a >> 21) as u8;
-    b77[1] = (n28a >> 13) as u8;
-    b77[2] = (n28a >> 5) as u8;
-    b77[3] = (n28a << 3) as u8 | (n28b >> 26) as u8;
-    b77[4] = (n28b >> 18) as u8;
-    b77[5] = (n28b >> 10) as u8;
-    b77[6] = (n28b >> 2) as u8;
-    b77[7] = (n28b << 6) as u8 | (igrid4 >> 10) as u8;
-    b77[8] = (igrid4 >> 2) as u8;
-    b77[9] = (igrid4 << 6) as u8 | (i3 << 3) as u8;
+// Pack Type 3 (ARRL RTTY Roundup), e.g. "TU; K1ABC W9XYZ R 579 WI" or "K1ABC W9XYZ 579 0013"
+pub fn packrttyru77(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(Ft8Payload::parse_rttyru(msg)?, hash_table, b77)
+}
 
-    0
+// Pack Type 5 (EU VHF contest), e.g. "<PA9XYZ> <G4ABC/P> R 590003 IO91NP"
+pub fn packeuvhf77(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(Ft8Payload::parse_euvhf(msg)?, hash_table, b77)
 }
 
-pub fn packtext77(text: &str, b77: &mut [u8; FTX_LDPC_K_BYTES]) {
-    let text = text.trim();
+// Pack free text of up to 13 characters as a base-42 number.
+pub fn packtext77(text: &str, b77: &mut [u8; FTX_LDPC_K_BYTES]) -> Result<(), Ft8Error> {
+    if text.len() > 13 || !text.chars().all(|c| A0.contains(c)) {
+        return Err(Ft8Error::InvalidText(text.to_string()));
+    }
 
     // Clear the first 72 bits representing a long number
-    for i in 0..9 {
-        b77[i] = 0;
+    for b in b77.iter_mut().take(9) {
+        *b = 0;
     }
 
     // Now express the text as base-42 number stored
     // in the first 72 bits of b77
-    for j in 0..13 {
+    let mut chars = text.chars();
+    for _ in 0..13 {
         // Multiply the long integer in b77 by 42
         let mut x = 0u16;
-        for i in (0..8).rev() {
+        for i in (0..9).rev() {
             x += b77[i] as u16 * 42u16;
             b77[i] = (x & 0xFF) as u8;
             x >>= 8;
         }
 
-        // Get the index of the current char
-        if j < text.len() {
-            if let Some(c) = text.chars().nth(j) {
-                if let Some(q) = A0.find(c) {
-                    x = if q > 0 { q as u16 } else { 0 };
-                } else {
-                    x = 0;
-                }
-            } else {
-                x = 0;
-            }
-        } else {
-            x = 0;
-        }
+        // Get the index of the current char, padding with spaces
+        x = match chars.next() {
+            Some(c) => A0.find(c).unwrap() as u16,
+            None => 0,
+        };
         // Here we double each added number in order to have the result multiplied
         // by two as well, so that it's a 71 bit number left-aligned in 72 bits (9 bytes)
         x <<= 1;
 
         // Now add the number to our long number
-        for i in (0..8).rev() {
+        for i in (0..9).rev() {
             if x == 0 {
                 break;
             }
@@ -230,21 +383,270 @@ pub fn packtext77(text: &str, b77: &mut [u8; FTX_LDPC_K_BYTES]) {
     // Set n3=0 (bits 71..73) and i3=0 (bits 74..76)
     b77[8] &= 0xFE;
     b77[9] &= 0x00;
+
+    Ok(())
 }
 
-pub fn pack77(msg: &str, c77: &mut [u8; FTX_LDPC_K_BYTES]) -> i32 {
-    // Check Type 1 (Standard 77-bit message) or Type 2, with optional "/P"
+// Pack a message of any type, and remember its callsigns for later hash lookups
+pub fn pack77(
+    msg: &str,
+    hash_table: &CallsignHashTable,
+    c77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    pack_payload(msg.parse()?, hash_table, c77)
+}
+
+fn pack_payload(
+    payload: Ft8Payload,
+    hash_table: &CallsignHashTable,
+    b77: &mut [u8; FTX_LDPC_K_BYTES],
+) -> Result<(), Ft8Error> {
+    *b77 = payload.to_bytes()?;
+    for call in payload.callsigns() {
+        hash_table.add(call);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::Call28;
+    use crate::unpack::*;
+
+    fn roundtrip28(token: &str) -> String {
+        let hash_table = CallsignHashTable::new();
+        let n28 = pack28(token, &hash_table).expect("cannot pack");
+        Call28::from_n28(n28, &hash_table)
+            .expect("cannot unpack")
+            .to_string()
+    }
+
+    #[test]
+    fn pack28_roundtrip() {
+        for token in [
+            "DE",
+            "QRZ",
+            "CQ",
+            "CQ 000",
+            "CQ 123",
+            "CQ DX",
+            "CQ POTA",
+            "CQ A",
+            "K1ABC",
+            "JA1ABC",
+            "W1AW",
+            "3DA0XYZ",
+            "3XA0XY",
+            "<PJ4/K1ABC>",
+        ] {
+            assert_eq!(roundtrip28(token), token);
+        }
+        assert_eq!(roundtrip28("CQ_DX"), "CQ DX");
+        for token in ["<...>", "CQ TOOLONG", "PJ4/K1ABC"] {
+            assert!(matches!(
+                pack28(token, &CallsignHashTable::new()),
+                Err(Ft8Error::InvalidCallsign(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn pack77_cq_modifier_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for msg in [
+            "CQ DX K1ABC FN42",
+            "CQ POTA JA1ABC",
+            "CQ 290 W1AW FN31",
+            "K1ABC W9XYZ R-08",
+            "K1ABC W9XYZ R FN42",
+        ] {
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77_1(msg, &hash_table, &mut b77).unwrap();
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
+    }
+
+    #[test]
+    fn callsign_shape() {
+        for call in ["K1ABC", "PJ4/K1ABC", "K1ABC/P", "YW18FIFA", "3DA0XYZ"] {
+            assert!(is_callsign(call), "{}", call);
+        }
+        for call in [
+            "73", "GL", "TNX", "TEST", "K1ABC/", "/K1ABC", "A/B/C1", "K1-ABC",
+        ] {
+            assert!(!is_callsign(call), "{}", call);
+        }
+    }
+
+    #[test]
+    fn pack77_nonstandard_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for msg in [
+            "<W9XYZ> PJ4/K1ABC RR73",
+            "PJ4/K1ABC <W9XYZ>",
+            "VP2E/W1AW <K1ABC> 73",
+            "CQ PJ4/K1ABC",
+            "CQ YW18FIFA",
+        ] {
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77(msg, &hash_table, &mut b77).unwrap();
+            assert_eq!((b77[9] >> 3) & 0x07, 4);
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
+
+        // The standard callsign is hashed when it is not bracketed
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        pack77("W9XYZ PJ4/K1ABC RRR", &hash_table, &mut b77).unwrap();
+        let mut text = String::new();
+        unpack77(&b77, &hash_table, &mut text).unwrap();
+        assert_eq!(text, "<W9XYZ> PJ4/K1ABC RRR");
+    }
+
+    #[test]
+    fn pack77_suffix_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for (msg, i3) in [
+            ("K1ABC/R W9XYZ EM37", 1),
+            ("W9XYZ K1ABC/R R-12", 1),
+            ("G4ABC/P PA9XYZ JO22", 2),
+            ("CQ G4ABC/P IO91", 2),
+            ("PA9XYZ/P G4ABC/P RR73", 2),
+        ] {
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77_1(msg, &hash_table, &mut b77).unwrap();
+            assert_eq!((b77[9] >> 3) & 0x07, i3);
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
 
-    if pack77_1(msg, c77) == 0 {
-        return 0;
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        assert!(matches!(
+            pack77_1("K1ABC/R G4ABC/P", &hash_table, &mut b77),
+            Err(Ft8Error::InvalidCallsign(_))
+        ));
     }
-    // TODO:
-    // Check 0.5 (telemetry)
 
-    // Check Type 4 (One nonstandard call and one hashed call)
+    #[test]
+    fn pack77_telemetry_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for msg in ["123456789ABCDEF012", "7FFFFFFFFFFFFFFFFF", "1", "BEEF"] {
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77(msg, &hash_table, &mut b77).unwrap();
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
 
-    // Default to free text
-    // i3=0 n3=0
-    packtext77(msg, c77);
-    0
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        // Out of the 71-bit range and too long
+        assert!(packtelemetry77("800000000000000000", &mut b77).is_err());
+        assert!(packtelemetry77("123456789ABCDEF0123", &mut b77).is_err());
+        assert!(packtelemetry77("TNX", &mut b77).is_err());
+    }
+
+    #[test]
+    fn pack77_dxpedition_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for msg in [
+            "K1ABC RR73; W9XYZ <KH1/KH7Z> -08",
+            "JA1ABC RR73; K1ABC <KH1/KH7Z> +32",
+        ] {
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77(msg, &hash_table, &mut b77).unwrap();
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
+
+        // The hashed callsign stays unresolved until it has been seen
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        packdxpedition77(
+            "K1ABC RR73; W9XYZ <VK9/W1AW> -30",
+            &CallsignHashTable::new(),
+            &mut b77,
+        )
+        .unwrap();
+        let mut text = String::new();
+        unpack77(&b77, &CallsignHashTable::new(), &mut text).unwrap();
+        assert_eq!(text, "K1ABC RR73; W9XYZ <...> -30");
+
+        // Odd reports are rounded down, also when negative
+        for (report, expected) in [("-29", "-30"), ("-07", "-08"), ("+07", "+06")] {
+            let msg = format!("K1ABC RR73; W9XYZ <KH1/KH7Z> {}", report);
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            packdxpedition77(&msg, &hash_table, &mut b77).unwrap();
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, format!("K1ABC RR73; W9XYZ <KH1/KH7Z> {}", expected));
+        }
+    }
+
+    #[test]
+    fn pack77_contest_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for (msg, i3, n3) in [
+            ("W9XYZ K1ABC R 3A EMA", 0, 3),
+            ("K1ABC W9XYZ 16F DX", 0, 3),
+            ("W9XYZ K1ABC 32B NB", 0, 4),
+            ("K1ABC W9XYZ 579 WI", 3, 0),
+            ("TU; K1ABC W9XYZ R 559 PEI", 3, 0),
+            ("W9XYZ K1ABC 529 0013", 3, 0),
+        ] {
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77(msg, &hash_table, &mut b77).unwrap();
+            assert_eq!((b77[9] >> 3) & 0x07, i3);
+            if i3 == 0 {
+                assert_eq!(((b77[8] << 2) & 0x04) | ((b77[9] >> 6) & 0x03), n3);
+            }
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
+    }
+
+    #[test]
+    fn pack77_euvhf_roundtrip() {
+        for msg in [
+            "<PA9XYZ> <G4ABC/P> R 590003 IO91NP",
+            "<G4ABC/P> <PA9XYZ> 522047 JO22DB",
+        ] {
+            let hash_table = CallsignHashTable::new();
+            let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+            pack77(msg, &hash_table, &mut b77).unwrap();
+            assert_eq!((b77[9] >> 3) & 0x07, 5);
+            let mut text = String::new();
+            unpack77(&b77, &hash_table, &mut text).unwrap();
+            assert_eq!(text, msg);
+        }
+
+        let hash_table = CallsignHashTable::new();
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        packeuvhf77("PA3XYZ G4ABC 590003 JO22DB", &hash_table, &mut b77).unwrap();
+        assert!(matches!(
+            packgrid6("JO22DY"),
+            Err(Ft8Error::GridOutOfRange(_))
+        ));
+        assert!(packgrid6("JO22").is_err());
+    }
+
+    #[test]
+    fn packtext77_limits() {
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        assert!(packtext77("TNX BOB 73 GL", &mut b77).is_ok());
+        assert!(matches!(
+            packtext77("TNX BOB 73 GL!", &mut b77),
+            Err(Ft8Error::InvalidText(_))
+        ));
+        assert!(packtext77("tnx", &mut b77).is_err());
+        assert!(matches!(
+            packgrid("R-51"),
+            Err(Ft8Error::ReportOutOfRange(_))
+        ));
+    }
 }
@@ -0,0 +1,1330 @@
+use crate::constant::*;
+use crate::error::Ft8Error;
+use crate::hashtable::{ihashcall, CallsignHashTable, HashType};
+use crate::pack::*;
+use crate::text::*;
+use crate::unpack::*;
+use std::fmt;
+use std::str::FromStr;
+
+// Modifier of a directed CQ, e.g. "CQ 290" or "CQ DX"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CqModifier {
+    Number(u16),
+    Letters(String),
+}
+
+// A callsign sent as a hash. The hash is kept so that the message packs back
+// to the same bits, the callsign is only known if it was found in the hash table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashedCall {
+    pub hash: u32,
+    pub call: Option<String>,
+}
+
+// Contents of a 28-bit callsign field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call28 {
+    De,
+    Qrz,
+    Cq(Option<CqModifier>),
+    // 22-bit hash of a nonstandard callsign
+    Hashed(HashedCall),
+    // Standard callsign, without /R or /P suffix
+    Standard(String),
+}
+
+// RRR, RR73 or 73
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Rrr,
+    Rr73,
+    R73,
+}
+
+// Last field of a standard message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extra {
+    Blank,
+    Grid(String),
+    Report(i32),
+    Reply(Reply),
+}
+
+// Exchange of an ARRL RTTY Roundup message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RttyExchange {
+    State(String),
+    Serial(u16),
+}
+
+// Contents of a 77-bit FT8 message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ft8Payload {
+    // 0.0 Free text of up to 13 characters
+    FreeText(String),
+    // 0.1 DXpedition mode, e.g. "K1ABC RR73; W9XYZ <KH1/KH7Z> -08"
+    Dxpedition {
+        call_rr73: Call28,
+        call_to: Call28,
+        call_de: HashedCall,
+        report: i32,
+    },
+    // 0.3, 0.4 ARRL Field Day, e.g. "W9XYZ K1ABC R 3A EMA"
+    FieldDay {
+        call_to: Call28,
+        call_de: Call28,
+        ack: bool,
+        transmitters: u8,
+        class: char,
+        section: String,
+    },
+    // 0.5 Telemetry, a 71-bit number
+    Telemetry(u128),
+    // 1, 2 Standard message, e.g. "CQ DX K1ABC/R FN42" or "K1ABC W9XYZ R-08".
+    // portable selects the /P (i3=2) rather than the /R (i3=1) suffix.
+    Standard {
+        call_to: Call28,
+        suffix_to: bool,
+        call_de: Call28,
+        suffix_de: bool,
+        portable: bool,
+        ack: bool,
+        extra: Extra,
+    },
+    // 3 ARRL RTTY Roundup, e.g. "TU; K1ABC W9XYZ R 579 WI"
+    RttyRoundup {
+        tu: bool,
+        call_to: Call28,
+        call_de: Call28,
+        ack: bool,
+        rst: u16,
+        exchange: RttyExchange,
+    },
+    // 4 One nonstandard call and one hashed call, e.g. "<W9XYZ> PJ4/K1ABC RR73".
+    // The hashed call comes first unless flip is set, and is not shown for CQ.
+    NonStandard {
+        call_hashed: HashedCall,
+        call: String,
+        flip: bool,
+        reply: Option<Reply>,
+        cq: bool,
+    },
+    // 5 EU VHF contest, e.g. "<PA9XYZ> <G4ABC/P> R 590003 IO91NP"
+    EuVhf {
+        call_to: HashedCall,
+        call_de: HashedCall,
+        ack: bool,
+        report: u8,
+        serial: u16,
+        grid: String,
+    },
+}
+
+impl fmt::Display for CqModifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CqModifier::Number(n) => write!(f, "{:03}", n),
+            CqModifier::Letters(letters) => f.write_str(letters),
+        }
+    }
+}
+
+impl HashedCall {
+    // Hash a callsign, with or without angle brackets
+    pub fn new(callsign: &str, hash_type: HashType) -> Result<Self, Ft8Error> {
+        let invalid = || Ft8Error::InvalidCallsign(callsign.to_string());
+        let call = strip_brackets(callsign).unwrap_or(callsign);
+        if call.is_empty() {
+            return Err(invalid());
+        }
+        Ok(HashedCall {
+            hash: ihashcall(call, hash_type).ok_or_else(invalid)?,
+            call: Some(call.to_string()),
+        })
+    }
+
+    // Resolve a received hash from the table
+    pub fn lookup(hash: u32, hash_type: HashType, hash_table: &CallsignHashTable) -> Self {
+        HashedCall {
+            hash,
+            call: hash_table.lookup(hash_type, hash),
+        }
+    }
+
+    // Check that the hash fits in its field
+    fn hash(&self, hash_type: HashType) -> Result<u64, Ft8Error> {
+        if self.hash >> hash_type.bits() != 0 {
+            return Err(Ft8Error::InvalidField {
+                field: "hash",
+                value: self.hash as u64,
+            });
+        }
+        Ok(self.hash as u64)
+    }
+}
+
+impl fmt::Display for HashedCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.call {
+            Some(call) => write!(f, "<{}>", call),
+            None => f.write_str("<...>"),
+        }
+    }
+}
+
+impl Call28 {
+    // Decode a 28-bit field. Values that no encoder produces are rejected,
+    // so that every decoded field packs back to the same bits.
+    pub fn from_n28(n28: u32, hash_table: &CallsignHashTable) -> Result<Self, Ft8Error> {
+        let invalid = || Ft8Error::InvalidField {
+            field: "n28",
+            value: n28 as u64,
+        };
+        let call = match n28 {
+            0 => Call28::De,
+            1 => Call28::Qrz,
+            2 => Call28::Cq(None),
+            // CQ_nnn with 3 digits
+            3..=1002 => Call28::Cq(Some(CqModifier::Number((n28 - 3) as u16))),
+            // CQ_aaaa with up to 4 letters
+            1003..=532443 => {
+                let mut n = n28 - 1003;
+                let mut aaaa = String::new();
+                for _ in 0..4 {
+                    aaaa.insert(0, charn((n % 27) as u8, 4));
+                    n /= 27;
+                }
+                Call28::Cq(Some(CqModifier::Letters(aaaa.trim().to_string())))
+            }
+            _ if n28 < NTOKENS => return Err(invalid()),
+            // 22-bit hash of a callsign
+            _ if n28 < NTOKENS + MAX22 => Call28::Hashed(HashedCall::lookup(
+                n28 - NTOKENS,
+                HashType::Hash22,
+                hash_table,
+            )),
+            // Standard callsign
+            _ => {
+                let mut n = n28 - NTOKENS - MAX22;
+                let mut c6 = String::new();
+                for (radix, table_idx) in [(27, 4), (27, 4), (27, 4), (10, 3), (36, 2), (37, 1)] {
+                    c6.insert(0, charn((n % radix) as u8, table_idx));
+                    n /= radix;
+                }
+                let callsign = c6.trim();
+                if callsign.is_empty() || callsign.contains(' ') {
+                    return Err(invalid());
+                }
+
+                // Undo the work-arounds for Swaziland (3DA0) and Guinea (3X) prefixes
+                let callsign = if let Some(rest) = callsign.strip_prefix("3D0") {
+                    format!("3DA0{}", rest)
+                } else if callsign.starts_with('Q')
+                    && callsign
+                        .chars()
+                        .nth(1)
+                        .is_some_and(|c| c.is_ascii_uppercase())
+                {
+                    format!("3X{}", &callsign[1..])
+                } else {
+                    callsign.to_string()
+                };
+                Call28::Standard(callsign)
+            }
+        };
+        match call.n28() {
+            Ok(n) if n == n28 => Ok(call),
+            _ => Err(invalid()),
+        }
+    }
+
+    pub fn n28(&self) -> Result<u32, Ft8Error> {
+        match self {
+            Call28::De => Ok(0),
+            Call28::Qrz => Ok(1),
+            Call28::Cq(None) => Ok(2),
+            Call28::Cq(Some(modifier)) => pack28_token(&format!("CQ {}", modifier)),
+            Call28::Hashed(hashed) => Ok(NTOKENS + hashed.hash(HashType::Hash22)? as u32),
+            Call28::Standard(callsign) => pack28_standard(callsign),
+        }
+    }
+
+    // Full callsign, if known
+    pub fn callsign(&self) -> Option<&str> {
+        match self {
+            Call28::Standard(callsign) => Some(callsign),
+            Call28::Hashed(hashed) => hashed.call.as_deref(),
+            _ => None,
+        }
+    }
+
+    // Parse a standard callsign only, as used by the contest and DXpedition messages
+    fn parse_standard(callsign: &str) -> Result<Self, Ft8Error> {
+        pack28_standard(callsign).map(|_| Call28::Standard(callsign.to_string()))
+    }
+}
+
+impl fmt::Display for Call28 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Call28::De => f.write_str("DE"),
+            Call28::Qrz => f.write_str("QRZ"),
+            Call28::Cq(None) => f.write_str("CQ"),
+            Call28::Cq(Some(modifier)) => write!(f, "CQ {}", modifier),
+            Call28::Hashed(hashed) => hashed.fmt(f),
+            Call28::Standard(callsign) => f.write_str(callsign),
+        }
+    }
+}
+
+impl FromStr for Call28 {
+    type Err = Ft8Error;
+
+    // Parse DE, QRZ, CQ, "CQ nnn" / "CQ_aaaa", <callsign> or a standard callsign
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        match token {
+            "DE" => Ok(Call28::De),
+            "QRZ" => Ok(Call28::Qrz),
+            "CQ" => Ok(Call28::Cq(None)),
+            _ => {
+                if let Some(modifier) = token
+                    .strip_prefix("CQ_")
+                    .or_else(|| token.strip_prefix("CQ "))
+                {
+                    if !is_cq_modifier(modifier) {
+                        return Err(Ft8Error::InvalidCallsign(token.to_string()));
+                    }
+                    Ok(Call28::Cq(Some(match modifier.parse() {
+                        Ok(nnn) => CqModifier::Number(nnn),
+                        Err(_) => CqModifier::Letters(modifier.to_string()),
+                    })))
+                } else if strip_brackets(token).is_some() {
+                    HashedCall::new(token, HashType::Hash22).map(Call28::Hashed)
+                } else {
+                    Call28::parse_standard(token)
+                }
+            }
+        }
+    }
+}
+
+impl Reply {
+    fn parse(token: &str) -> Option<Self> {
+        match token {
+            "RRR" => Some(Reply::Rrr),
+            "RR73" => Some(Reply::Rr73),
+            "73" => Some(Reply::R73),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reply::Rrr => "RRR",
+            Reply::Rr73 => "RR73",
+            Reply::R73 => "73",
+        })
+    }
+}
+
+impl Extra {
+    // Decode the 15-bit igrid4 field (without the ir bit)
+    fn from_igrid4(igrid4: u16) -> Result<Self, Ft8Error> {
+        if igrid4 < MAXGRID4 {
+            let mut n = igrid4;
+            let mut grid = String::new();
+            for (radix, base) in [(10, b'0'), (10, b'0'), (18, b'A'), (18, b'A')] {
+                grid.insert(0, (base + (n % radix) as u8) as char);
+                n /= radix;
+            }
+            return Ok(Extra::Grid(grid));
+        }
+        match igrid4 - MAXGRID4 {
+            1 => Ok(Extra::Blank),
+            2 => Ok(Extra::Reply(Reply::Rrr)),
+            3 => Ok(Extra::Reply(Reply::Rr73)),
+            4 => Ok(Extra::Reply(Reply::R73)),
+            // Reports below -30 are stored above +49, +50 is never sent
+            irpt @ (5..=84 | 86..=105) => {
+                let isnr = irpt as i32 - 35;
+                Ok(Extra::Report(if isnr > 50 { isnr - 101 } else { isnr }))
+            }
+            _ => Err(Ft8Error::InvalidField {
+                field: "igrid4",
+                value: igrid4 as u64,
+            }),
+        }
+    }
+
+    fn igrid4(&self) -> Result<u16, Ft8Error> {
+        match self {
+            Extra::Blank => Ok(MAXGRID4 + 1),
+            Extra::Reply(Reply::Rrr) => Ok(MAXGRID4 + 2),
+            Extra::Reply(Reply::Rr73) => Ok(MAXGRID4 + 3),
+            Extra::Reply(Reply::R73) => Ok(MAXGRID4 + 4),
+            // Computed from the letters and digits rather than with packgrid,
+            // which would take the square "RR73" for the reply
+            Extra::Grid(grid) => {
+                let out_of_range = || Ft8Error::GridOutOfRange(grid.clone());
+                if grid.len() != 4 {
+                    return Err(out_of_range());
+                }
+                let mut igrid4 = 0u16;
+                for (c, (radix, base)) in
+                    grid.bytes()
+                        .zip([(18, b'A'), (18, b'A'), (10, b'0'), (10, b'0')])
+                {
+                    let digit = c.wrapping_sub(base) as u16;
+                    if digit >= radix {
+                        return Err(out_of_range());
+                    }
+                    igrid4 = igrid4 * radix + digit;
+                }
+                Ok(igrid4)
+            }
+            Extra::Report(dd) if (-50..=49).contains(dd) => {
+                let dd = if *dd < -30 { dd + 101 } else { *dd };
+                Ok(MAXGRID4 + (35 + dd) as u16)
+            }
+            Extra::Report(dd) => Err(Ft8Error::ReportOutOfRange(dd.to_string())),
+        }
+    }
+}
+
+impl RttyExchange {
+    fn s13(&self) -> Result<u64, Ft8Error> {
+        match self {
+            RttyExchange::State(state) => RTTY_MULTIPLIERS
+                .iter()
+                .position(|s| s == state)
+                .map(|imult| 8001 + imult as u64)
+                .ok_or_else(|| Ft8Error::InvalidExchange(state.clone())),
+            RttyExchange::Serial(serial) if *serial <= 8000 => Ok(*serial as u64),
+            RttyExchange::Serial(serial) => Err(Ft8Error::InvalidExchange(serial.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for RttyExchange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RttyExchange::State(state) => f.write_str(state),
+            RttyExchange::Serial(serial) => write!(f, "{:04}", serial),
+        }
+    }
+}
+
+impl Ft8Payload {
+    // Unpack a 77-bit message. Callsigns found in the message are added to the hash table.
+    pub fn from_bytes(
+        a77: &[u8; FTX_LDPC_K_BYTES],
+        hash_table: &CallsignHashTable,
+    ) -> Result<Self, Ft8Error> {
+        let i3 = get_bits(a77, 74, 3);
+        let n3 = get_bits(a77, 71, 3);
+
+        let payload = match (i3, n3) {
+            (0, 0) => Ft8Payload::FreeText(unpack_text(a77)?),
+            (0, 1) => Ft8Payload::Dxpedition {
+                call_rr73: Call28::from_n28(get_bits(a77, 0, 28) as u32, hash_table)?,
+                call_to: Call28::from_n28(get_bits(a77, 28, 28) as u32, hash_table)?,
+                call_de: HashedCall::lookup(
+                    get_bits(a77, 56, 10) as u32,
+                    HashType::Hash10,
+                    hash_table,
+                ),
+                report: 2 * get_bits(a77, 66, 5) as i32 - 30,
+            },
+            (0, 3) | (0, 4) => {
+                let n4 = get_bits(a77, 57, 4) as u8;
+                let k3 = get_bits(a77, 61, 3);
+                let isec = get_bits(a77, 64, 7);
+                if k3 > 5 {
+                    return Err(Ft8Error::InvalidField {
+                        field: "k3",
+                        value: k3,
+                    });
+                }
+                if isec < 1 || isec as usize > ARRL_SECTIONS.len() {
+                    return Err(Ft8Error::InvalidField {
+                        field: "isec",
+                        value: isec,
+                    });
+                }
+                Ft8Payload::FieldDay {
+                    call_to: Call28::from_n28(get_bits(a77, 0, 28) as u32, hash_table)?,
+                    call_de: Call28::from_n28(get_bits(a77, 28, 28) as u32, hash_table)?,
+                    ack: get_bits(a77, 56, 1) != 0,
+                    transmitters: if n3 == 3 { n4 + 1 } else { n4 + 17 },
+                    class: (b'A' + k3 as u8) as char,
+                    section: ARRL_SECTIONS[isec as usize - 1].to_string(),
+                }
+            }
+            (0, 5) => Ft8Payload::Telemetry(
+                (get_bits(a77, 0, 7) as u128) << 64 | get_bits(a77, 7, 64) as u128,
+            ),
+            (1, _) | (2, _) => {
+                let n29a = get_bits(a77, 0, 29) as u32;
+                let n29b = get_bits(a77, 29, 29) as u32;
+                Ft8Payload::Standard {
+                    call_to: Call28::from_n28(n29a >> 1, hash_table)?,
+                    suffix_to: n29a & 1 != 0,
+                    call_de: Call28::from_n28(n29b >> 1, hash_table)?,
+                    suffix_de: n29b & 1 != 0,
+                    portable: i3 == 2,
+                    ack: get_bits(a77, 58, 1) != 0,
+                    extra: Extra::from_igrid4(get_bits(a77, 59, 15) as u16)?,
+                }
+            }
+            (3, _) => {
+                let s13 = get_bits(a77, 61, 13);
+                let exchange = match s13 {
+                    0..=8000 => RttyExchange::Serial(s13 as u16),
+                    _ => RttyExchange::State(
+                        RTTY_MULTIPLIERS
+                            .get(s13 as usize - 8001)
+                            .ok_or(Ft8Error::InvalidField {
+                                field: "s13",
+                                value: s13,
+                            })?
+                            .to_string(),
+                    ),
+                };
+                Ft8Payload::RttyRoundup {
+                    tu: get_bits(a77, 0, 1) != 0,
+                    call_to: Call28::from_n28(get_bits(a77, 1, 28) as u32, hash_table)?,
+                    call_de: Call28::from_n28(get_bits(a77, 29, 28) as u32, hash_table)?,
+                    ack: get_bits(a77, 57, 1) != 0,
+                    rst: 529 + 10 * get_bits(a77, 58, 3) as u16,
+                    exchange,
+                }
+            }
+            (4, _) => Ft8Payload::NonStandard {
+                call_hashed: HashedCall::lookup(
+                    get_bits(a77, 0, 12) as u32,
+                    HashType::Hash12,
+                    hash_table,
+                ),
+                call: unpack58(get_bits(a77, 12, 58))?,
+                flip: get_bits(a77, 70, 1) != 0,
+                reply: match get_bits(a77, 71, 2) {
+                    1 => Some(Reply::Rrr),
+                    2 => Some(Reply::Rr73),
+                    3 => Some(Reply::R73),
+                    _ => None,
+                },
+                cq: get_bits(a77, 73, 1) != 0,
+            },
+            (5, _) => Ft8Payload::EuVhf {
+                call_to: HashedCall::lookup(
+                    get_bits(a77, 0, 12) as u32,
+                    HashType::Hash12,
+                    hash_table,
+                ),
+                call_de: HashedCall::lookup(
+                    get_bits(a77, 12, 22) as u32,
+                    HashType::Hash22,
+                    hash_table,
+                ),
+                ack: get_bits(a77, 34, 1) != 0,
+                report: 52 + get_bits(a77, 35, 3) as u8,
+                serial: get_bits(a77, 38, 11) as u16,
+                grid: unpackgrid6(get_bits(a77, 49, 25) as u32)?,
+            },
+            _ => {
+                return Err(Ft8Error::UnsupportedMessageType {
+                    i3: i3 as u8,
+                    n3: n3 as u8,
+                })
+            }
+        };
+
+        // Remember the full callsigns so that later hashes can be resolved
+        for call in payload.callsigns() {
+            hash_table.add(call);
+        }
+        Ok(payload)
+    }
+
+    // Pack the message into 77 bits
+    pub fn to_bytes(&self) -> Result<[u8; FTX_LDPC_K_BYTES], Ft8Error> {
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        match self {
+            Ft8Payload::FreeText(text) => packtext77(text, &mut b77)?,
+            Ft8Payload::Dxpedition {
+                call_rr73,
+                call_to,
+                call_de,
+                report,
+            } => {
+                if !(-30..=32).contains(report) {
+                    return Err(Ft8Error::ReportOutOfRange(report.to_string()));
+                }
+                // Pack into 28 + 28 + 10 + 5 + 3 + 3 bits
+                put_bits(&mut b77, 0, 28, call_rr73.n28()? as u64);
+                put_bits(&mut b77, 28, 28, call_to.n28()? as u64);
+                put_bits(&mut b77, 56, 10, call_de.hash(HashType::Hash10)?);
+                put_bits(&mut b77, 66, 5, ((report + 30) / 2) as u64);
+                put_bits(&mut b77, 71, 3, 1); // n3 = 1
+            }
+            Ft8Payload::FieldDay {
+                call_to,
+                call_de,
+                ack,
+                transmitters,
+                class,
+                section,
+            } => {
+                let isec = ARRL_SECTIONS
+                    .iter()
+                    .position(|s| s == section)
+                    .ok_or_else(|| Ft8Error::InvalidExchange(section.clone()))?;
+                if !(1..=32).contains(transmitters) || !in_range(*class, 'A', 'F') {
+                    return Err(Ft8Error::InvalidExchange(format!(
+                        "{}{}",
+                        transmitters, class
+                    )));
+                }
+                // Up to 16 transmitters use n3=3, more use n3=4
+                let (n3, n4) = if *transmitters <= 16 {
+                    (3, transmitters - 1)
+                } else {
+                    (4, transmitters - 17)
+                };
+                // Pack into 28 + 28 + 1 + 4 + 3 + 7 + 3 + 3 bits
+                put_bits(&mut b77, 0, 28, call_to.n28()? as u64);
+                put_bits(&mut b77, 28, 28, call_de.n28()? as u64);
+                put_bits(&mut b77, 56, 1, *ack as u64);
+                put_bits(&mut b77, 57, 4, n4 as u64);
+                put_bits(&mut b77, 61, 3, (*class as u8 - b'A') as u64);
+                put_bits(&mut b77, 64, 7, isec as u64 + 1);
+                put_bits(&mut b77, 71, 3, n3);
+            }
+            Ft8Payload::Telemetry(n71) => {
+                if n71 >> 71 != 0 {
+                    return Err(Ft8Error::InvalidText(format!("{:X}", n71)));
+                }
+                // Pack into 71 + 3 + 3 bits
+                put_bits(&mut b77, 0, 7, (n71 >> 64) as u64);
+                put_bits(&mut b77, 7, 64, *n71 as u64);
+                put_bits(&mut b77, 71, 3, 5); // n3 = 5
+            }
+            Ft8Payload::Standard {
+                call_to,
+                suffix_to,
+                call_de,
+                suffix_de,
+                portable,
+                ack,
+                extra,
+            } => {
+                // Pack into (28 + 1) + (28 + 1) + (1 + 15) + 3 bits
+                put_bits(&mut b77, 0, 28, call_to.n28()? as u64);
+                put_bits(&mut b77, 28, 1, *suffix_to as u64);
+                put_bits(&mut b77, 29, 28, call_de.n28()? as u64);
+                put_bits(&mut b77, 57, 1, *suffix_de as u64);
+                put_bits(&mut b77, 58, 1, *ack as u64);
+                put_bits(&mut b77, 59, 15, extra.igrid4()? as u64);
+                put_bits(&mut b77, 74, 3, if *portable { 2 } else { 1 });
+            }
+            Ft8Payload::RttyRoundup {
+                tu,
+                call_to,
+                call_de,
+                ack,
+                rst,
+                exchange,
+            } => {
+                // RST 529 - 599
+                if !(529..=599).contains(rst) || rst % 10 != 9 {
+                    return Err(Ft8Error::ReportOutOfRange(rst.to_string()));
+                }
+                // Pack into 1 + 28 + 28 + 1 + 3 + 13 + 3 bits
+                put_bits(&mut b77, 0, 1, *tu as u64);
+                put_bits(&mut b77, 1, 28, call_to.n28()? as u64);
+                put_bits(&mut b77, 29, 28, call_de.n28()? as u64);
+                put_bits(&mut b77, 57, 1, *ack as u64);
+                put_bits(&mut b77, 58, 3, ((rst - 529) / 10) as u64);
+                put_bits(&mut b77, 61, 13, exchange.s13()?);
+                put_bits(&mut b77, 74, 3, 3); // i3 = 3
+            }
+            Ft8Payload::NonStandard {
+                call_hashed,
+                call,
+                flip,
+                reply,
+                cq,
+            } => {
+                let nrpt = match reply {
+                    None => 0,
+                    Some(Reply::Rrr) => 1,
+                    Some(Reply::Rr73) => 2,
+                    Some(Reply::R73) => 3,
+                };
+                // Pack into 12 + 58 + 1 + 2 + 1 + 3 bits
+                put_bits(&mut b77, 0, 12, call_hashed.hash(HashType::Hash12)?);
+                put_bits(&mut b77, 12, 58, pack58(call)?);
+                put_bits(&mut b77, 70, 1, *flip as u64);
+                put_bits(&mut b77, 71, 2, nrpt);
+                put_bits(&mut b77, 73, 1, *cq as u64);
+                put_bits(&mut b77, 74, 3, 4); // i3 = 4
+            }
+            Ft8Payload::EuVhf {
+                call_to,
+                call_de,
+                ack,
+                report,
+                serial,
+                grid,
+            } => {
+                if !(52..=59).contains(report) {
+                    return Err(Ft8Error::ReportOutOfRange(report.to_string()));
+                }
+                if *serial > 2047 {
+                    return Err(Ft8Error::InvalidExchange(serial.to_string()));
+                }
+                // Pack into 12 + 22 + 1 + 3 + 11 + 25 + 3 bits
+                put_bits(&mut b77, 0, 12, call_to.hash(HashType::Hash12)?);
+                put_bits(&mut b77, 12, 22, call_de.hash(HashType::Hash22)?);
+                put_bits(&mut b77, 34, 1, *ack as u64);
+                put_bits(&mut b77, 35, 3, (report - 52) as u64);
+                put_bits(&mut b77, 38, 11, *serial as u64);
+                put_bits(&mut b77, 49, 25, packgrid6(grid)? as u64);
+                put_bits(&mut b77, 74, 3, 5); // i3 = 5
+            }
+        }
+        Ok(b77)
+    }
+
+    // Full callsigns carried by the message, including resolved hashes
+    pub fn callsigns(&self) -> Vec<&str> {
+        match self {
+            Ft8Payload::FreeText(_) | Ft8Payload::Telemetry(_) => vec![],
+            Ft8Payload::Dxpedition {
+                call_rr73,
+                call_to,
+                call_de,
+                ..
+            } => [
+                Call28::callsign(call_rr73),
+                Call28::callsign(call_to),
+                call_de.call.as_deref(),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            Ft8Payload::FieldDay {
+                call_to, call_de, ..
+            }
+            | Ft8Payload::Standard {
+                call_to, call_de, ..
+            }
+            | Ft8Payload::RttyRoundup {
+                call_to, call_de, ..
+            } => [Call28::callsign(call_to), Call28::callsign(call_de)]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Ft8Payload::NonStandard {
+                call_hashed, call, ..
+            } => [call_hashed.call.as_deref(), Some(call.as_str())]
+                .into_iter()
+                .flatten()
+                .collect(),
+            Ft8Payload::EuVhf {
+                call_to, call_de, ..
+            } => [call_to.call.as_deref(), call_de.call.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+        }
+    }
+
+    pub fn is_cq(&self) -> bool {
+        matches!(
+            self,
+            Ft8Payload::Standard {
+                call_to: Call28::Cq(_),
+                ..
+            } | Ft8Payload::NonStandard { cq: true, .. }
+        )
+    }
+
+    // Signal report in dB, if the message carries one
+    pub fn report(&self) -> Option<i32> {
+        match self {
+            Ft8Payload::Standard {
+                extra: Extra::Report(report),
+                ..
+            }
+            | Ft8Payload::Dxpedition { report, .. } => Some(*report),
+            _ => None,
+        }
+    }
+
+    // Grid locator, if the message carries one
+    pub fn grid(&self) -> Option<&str> {
+        match self {
+            Ft8Payload::Standard {
+                extra: Extra::Grid(grid),
+                ..
+            }
+            | Ft8Payload::EuVhf { grid, .. } => Some(grid),
+            _ => None,
+        }
+    }
+
+    // Type 1 (standard message) and Type 2 (ditto, with a "/P" call)
+    pub fn parse_standard(msg: &str) -> Result<Self, Ft8Error> {
+        let mismatch = || Ft8Error::InvalidMessage(msg.to_string());
+        let mut token: Vec<&str> = msg.split_whitespace().collect();
+
+        // Join a CQ modifier such as "CQ DX" into a single token
+        let cq;
+        if token.len() > 2 && token[0] == "CQ" && is_cq_modifier(token[1]) {
+            cq = format!("CQ {}", token[1]);
+            token.splice(0..2, [cq.as_str()]);
+        }
+        if token.len() < 2 || token.len() > 4 {
+            return Err(mismatch());
+        }
+
+        // Check for /R (rover) and /P (portable) suffixes
+        let rover = token[..2].iter().any(|t| t.ends_with("/R"));
+        let portable = token[..2].iter().any(|t| t.ends_with("/P"));
+        if rover && portable {
+            // /R and /P cannot be mixed in one message
+            return Err(Ft8Error::InvalidCallsign(token[..2].join(" ")));
+        }
+        let call = |token: &str| -> Result<(Call28, bool), Ft8Error> {
+            match token
+                .strip_suffix("/R")
+                .or_else(|| token.strip_suffix("/P"))
+            {
+                Some(base) => Ok((Call28::parse_standard(base)?, true)),
+                None => Ok((token.parse()?, false)),
+            }
+        };
+        let (call_to, suffix_to) = call(token[0])?;
+        let (call_de, suffix_de) = call(token[1])?;
+
+        let igrid4 = match token[2..] {
+            // Two callsigns, no grid/report
+            [] => packgrid(" ")?,
+            [grid] => packgrid(grid)?,
+            // R followed by a grid
+            ["R", grid] => match packgrid(grid)? {
+                igrid4 if igrid4 < MAXGRID4 => igrid4 | 0x8000,
+                _ => return Err(Ft8Error::GridOutOfRange(grid.to_string())),
+            },
+            _ => return Err(mismatch()),
+        };
+
+        Ok(Ft8Payload::Standard {
+            call_to,
+            suffix_to,
+            call_de,
+            suffix_de,
+            portable,
+            ack: igrid4 & 0x8000 != 0,
+            extra: Extra::from_igrid4(igrid4 & 0x7FFF)?,
+        })
+    }
+
+    // Type 0.5 (telemetry): up to 18 hex digits holding a 71-bit number
+    pub fn parse_telemetry(msg: &str) -> Result<Self, Ft8Error> {
+        let telemetry = msg.trim();
+        if telemetry.is_empty()
+            || telemetry.len() > 18
+            || !telemetry
+                .chars()
+                .all(|c| c.is_ascii_digit() || in_range(c, 'A', 'F'))
+        {
+            return Err(Ft8Error::InvalidMessage(msg.to_string()));
+        }
+        let n71 = u128::from_str_radix(telemetry, 16)
+            .map_err(|_| Ft8Error::InvalidText(telemetry.to_string()))?;
+        // Must fit in 71 bits
+        if n71 >> 71 != 0 {
+            return Err(Ft8Error::InvalidText(telemetry.to_string()));
+        }
+        Ok(Ft8Payload::Telemetry(n71))
+    }
+
+    // Type 0.1 (DXpedition mode), e.g. "K1ABC RR73; W9XYZ <KH1/KH7Z> -08"
+    // Odd reports are rounded down to the next even value (-29 -> -30, +07 -> +06).
+    pub fn parse_dxpedition(msg: &str) -> Result<Self, Ft8Error> {
+        let token: Vec<&str> = msg.split_whitespace().collect();
+        let [call_1, "RR73;", call_2, call_3, report] = token[..] else {
+            return Err(Ft8Error::InvalidMessage(msg.to_string()));
+        };
+        let report = match dd_to_int(report) {
+            Some(dd) if report.starts_with(['+', '-']) && (-30..=32).contains(&dd) => {
+                (dd + 30) / 2 * 2 - 30
+            }
+            _ => return Err(Ft8Error::ReportOutOfRange(report.to_string())),
+        };
+        Ok(Ft8Payload::Dxpedition {
+            call_rr73: Call28::parse_standard(call_1)?,
+            call_to: Call28::parse_standard(call_2)?,
+            call_de: HashedCall::new(call_3, HashType::Hash10)?,
+            report,
+        })
+    }
+
+    // Type 0.3 and 0.4 (ARRL Field Day), e.g. "W9XYZ K1ABC R 3A EMA"
+    pub fn parse_fieldday(msg: &str) -> Result<Self, Ft8Error> {
+        let mismatch = || Ft8Error::InvalidMessage(msg.to_string());
+        let token: Vec<&str> = msg.split_whitespace().collect();
+        let (call_1, call_2, ack, class, section) = match token[..] {
+            [call_1, call_2, "R", class, section] => (call_1, call_2, true, class, section),
+            [call_1, call_2, class, section] => (call_1, call_2, false, class, section),
+            _ => return Err(mismatch()),
+        };
+
+        // Class: number of transmitters (1-32) followed by a letter A-F
+        let letter = class
+            .chars()
+            .last()
+            .filter(|c| in_range(*c, 'A', 'F'))
+            .ok_or_else(mismatch)?;
+        let transmitters = class[..class.len() - 1]
+            .parse::<u8>()
+            .map_err(|_| mismatch())?;
+        if !(1..=32).contains(&transmitters) {
+            return Err(Ft8Error::InvalidExchange(class.to_string()));
+        }
+        if !ARRL_SECTIONS.contains(&section) {
+            return Err(Ft8Error::InvalidExchange(section.to_string()));
+        }
+
+        Ok(Ft8Payload::FieldDay {
+            call_to: Call28::parse_standard(call_1)?,
+            call_de: Call28::parse_standard(call_2)?,
+            ack,
+            transmitters,
+            class: letter,
+            section: section.to_string(),
+        })
+    }
+
+    // Type 3 (ARRL RTTY Roundup), e.g. "TU; K1ABC W9XYZ R 579 WI" or "K1ABC W9XYZ 579 0013"
+    pub fn parse_rttyru(msg: &str) -> Result<Self, Ft8Error> {
+        let mismatch = || Ft8Error::InvalidMessage(msg.to_string());
+        let mut token: Vec<&str> = msg.split_whitespace().collect();
+        let tu = token.first() == Some(&"TU;");
+        if tu {
+            token.remove(0);
+        }
+        let (call_1, call_2, ack, report, exchange) = match token[..] {
+            [call_1, call_2, "R", report, exchange] => (call_1, call_2, true, report, exchange),
+            [call_1, call_2, report, exchange] => (call_1, call_2, false, report, exchange),
+            _ => return Err(mismatch()),
+        };
+
+        // RST 529 - 599
+        let rst: Vec<char> = report.chars().collect();
+        if rst.len() != 3 || rst[0] != '5' || !in_range(rst[1], '2', '9') || rst[2] != '9' {
+            return Err(mismatch());
+        }
+
+        // Exchange: a state/province or a serial number 1 - 7999
+        let exchange = if RTTY_MULTIPLIERS.contains(&exchange) {
+            RttyExchange::State(exchange.to_string())
+        } else if exchange.len() <= 4 && exchange.chars().all(|c| c.is_ascii_digit()) {
+            match exchange.parse::<u16>() {
+                Ok(serial) if (1..=7999).contains(&serial) => RttyExchange::Serial(serial),
+                _ => return Err(Ft8Error::InvalidExchange(exchange.to_string())),
+            }
+        } else {
+            return Err(mismatch());
+        };
+
+        Ok(Ft8Payload::RttyRoundup {
+            tu,
+            call_to: Call28::parse_standard(call_1)?,
+            call_de: Call28::parse_standard(call_2)?,
+            ack,
+            rst: report.parse().map_err(|_| mismatch())?,
+            exchange,
+        })
+    }
+
+    // Type 5 (EU VHF contest), e.g. "<PA9XYZ> <G4ABC/P> R 590003 IO91NP"
+    // Both callsigns are sent as hashes, the brackets are optional.
+    pub fn parse_euvhf(msg: &str) -> Result<Self, Ft8Error> {
+        let mismatch = || Ft8Error::InvalidMessage(msg.to_string());
+        let token: Vec<&str> = msg.split_whitespace().collect();
+        let (call_1, call_2, ack, exchange, grid6) = match token[..] {
+            [call_1, call_2, "R", exchange, grid6] => (call_1, call_2, true, exchange, grid6),
+            [call_1, call_2, exchange, grid6] => (call_1, call_2, false, exchange, grid6),
+            _ => return Err(mismatch()),
+        };
+
+        // Exchange: RS (52 - 59) followed by a 4 digit serial number (0 - 2047)
+        if exchange.len() != 6 || !exchange.chars().all(|c| c.is_ascii_digit()) {
+            return Err(mismatch());
+        }
+        let report: u8 = exchange[..2].parse().map_err(|_| mismatch())?;
+        let serial: u16 = exchange[2..].parse().map_err(|_| mismatch())?;
+        if !(52..=59).contains(&report) {
+            return Err(Ft8Error::ReportOutOfRange(exchange[..2].to_string()));
+        }
+        if serial > 2047 {
+            return Err(Ft8Error::InvalidExchange(exchange.to_string()));
+        }
+        packgrid6(grid6)?;
+
+        Ok(Ft8Payload::EuVhf {
+            call_to: HashedCall::new(call_1, HashType::Hash12)?,
+            call_de: HashedCall::new(call_2, HashType::Hash22)?,
+            ack,
+            report,
+            serial,
+            grid: grid6.to_string(),
+        })
+    }
+
+    // Type 4 (One nonstandard call and one hashed call)
+    // e.g. "<W9XYZ> PJ4/K1ABC RR73", "PJ4/K1ABC <W9XYZ>" or "CQ PJ4/K1ABC"
+    pub fn parse_nonstandard(msg: &str) -> Result<Self, Ft8Error> {
+        let mismatch = || Ft8Error::InvalidMessage(msg.to_string());
+        let token: Vec<&str> = msg.split_whitespace().collect();
+        if token.len() < 2 || token.len() > 3 {
+            return Err(mismatch());
+        }
+
+        if token[0] == "CQ" && token.len() == 2 {
+            if !is_callsign(token[1]) {
+                return Err(mismatch());
+            }
+            pack58(token[1])?;
+            return Ok(Ft8Payload::NonStandard {
+                call_hashed: HashedCall {
+                    hash: 0,
+                    call: None,
+                },
+                call: token[1].to_string(),
+                flip: false,
+                reply: None,
+                cq: true,
+            });
+        }
+
+        let reply = match token.get(2) {
+            Some(reply) => Some(Reply::parse(reply).ok_or_else(mismatch)?),
+            None => None,
+        };
+        let standard = |call: &str| pack28_token(call).is_ok();
+        // The hashed callsign comes first unless flip is set
+        let (call_hashed, call, flip) = match (strip_brackets(token[0]), strip_brackets(token[1])) {
+            (Some(call), None) => (call, token[1], false),
+            (None, Some(call)) => (call, token[0], true),
+            // A standard callsign next to a nonstandard one is sent as a hash
+            (None, None) if standard(token[0]) && !standard(token[1]) => {
+                (token[0], token[1], false)
+            }
+            (None, None) if !standard(token[0]) && standard(token[1]) => (token[1], token[0], true),
+            _ => return Err(mismatch()),
+        };
+        // Both tokens must look like callsigns, or "GL 73" would be sent as a hashed "73"
+        if !is_callsign(call_hashed) || !is_callsign(call) {
+            return Err(mismatch());
+        }
+        pack58(call)?;
+
+        Ok(Ft8Payload::NonStandard {
+            call_hashed: HashedCall::new(call_hashed, HashType::Hash12)?,
+            call: call.to_string(),
+            flip,
+            reply,
+            cq: false,
+        })
+    }
+}
+
+// Parser for one message type
+type ParseFn = fn(&str) -> Result<Ft8Payload, Ft8Error>;
+
+impl FromStr for Ft8Payload {
+    type Err = Ft8Error;
+
+    // Try each message type in the same order as WSJT-X's pack77
+    // (0.1, 0.3/0.4, 0.5, 1/2, 3, 4, 5), falling back to free text.
+    // If nothing fits, report the first error that is more specific than a shape mismatch.
+    fn from_str(msg: &str) -> Result<Self, Self::Err> {
+        let parsers: [ParseFn; 7] = [
+            Ft8Payload::parse_dxpedition,
+            Ft8Payload::parse_fieldday,
+            Ft8Payload::parse_telemetry,
+            Ft8Payload::parse_standard,
+            Ft8Payload::parse_rttyru,
+            Ft8Payload::parse_nonstandard,
+            Ft8Payload::parse_euvhf,
+        ];
+        let mut first_error = None;
+        for parse in parsers {
+            match parse(msg) {
+                Ok(payload) => return Ok(payload),
+                Err(Ft8Error::InvalidMessage(_)) => {}
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        let text = Ft8Payload::FreeText(msg.trim().to_string());
+        match text.to_bytes() {
+            Ok(_) => Ok(text),
+            Err(e) => Err(first_error.unwrap_or(e)),
+        }
+    }
+}
+
+impl fmt::Display for Ft8Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = |ack: &bool| if *ack { "R " } else { "" };
+        match self {
+            Ft8Payload::FreeText(text) => f.write_str(text.trim()),
+            Ft8Payload::Dxpedition {
+                call_rr73,
+                call_to,
+                call_de,
+                report,
+            } => write!(
+                f,
+                "{} RR73; {} {} {:+03}",
+                call_rr73, call_to, call_de, report
+            ),
+            Ft8Payload::FieldDay {
+                call_to,
+                call_de,
+                ack,
+                transmitters,
+                class,
+                section,
+            } => write!(
+                f,
+                "{} {} {}{}{} {}",
+                call_to,
+                call_de,
+                r(ack),
+                transmitters,
+                class,
+                section
+            ),
+            Ft8Payload::Telemetry(n71) => write!(f, "{:X}", n71),
+            Ft8Payload::Standard {
+                call_to,
+                suffix_to,
+                call_de,
+                suffix_de,
+                portable,
+                ack,
+                extra,
+            } => {
+                let suffix = |ip: &bool| match (ip, portable) {
+                    (false, _) => "",
+                    (true, false) => "/R",
+                    (true, true) => "/P",
+                };
+                write!(
+                    f,
+                    "{}{} {}{}",
+                    call_to,
+                    suffix(suffix_to),
+                    call_de,
+                    suffix(suffix_de)
+                )?;
+                match extra {
+                    Extra::Blank => Ok(()),
+                    Extra::Grid(grid) => write!(f, " {}{}", r(ack), grid),
+                    Extra::Report(report) => {
+                        let mut dd = String::new();
+                        int_to_dd(&mut dd, *report, true);
+                        write!(f, " {}{}", if *ack { "R" } else { "" }, dd)
+                    }
+                    Extra::Reply(reply) => write!(f, " {}", reply),
+                }
+            }
+            Ft8Payload::RttyRoundup {
+                tu,
+                call_to,
+                call_de,
+                ack,
+                rst,
+                exchange,
+            } => write!(
+                f,
+                "{}{} {} {}{} {}",
+                if *tu { "TU; " } else { "" },
+                call_to,
+                call_de,
+                r(ack),
+                rst,
+                exchange
+            ),
+            Ft8Payload::NonStandard {
+                call_hashed,
+                call,
+                flip,
+                reply,
+                cq,
+            } => {
+                if *cq {
+                    return write!(f, "CQ {}", call);
+                }
+                if *flip {
+                    write!(f, "{} {}", call, call_hashed)?;
+                } else {
+                    write!(f, "{} {}", call_hashed, call)?;
+                }
+                match reply {
+                    Some(reply) => write!(f, " {}", reply),
+                    None => Ok(()),
+                }
+            }
+            Ft8Payload::EuVhf {
+                call_to,
+                call_de,
+                ack,
+                report,
+                serial,
+                grid,
+            } => write!(
+                f,
+                "{} {} {}{}{:04} {}",
+                call_to,
+                call_de,
+                r(ack),
+                report,
+                serial,
+                grid
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::pack77;
+    use crate::unpack::unpack77;
+
+    fn roundtrip(msg: &str, hash_table: &CallsignHashTable) -> [u8; FTX_LDPC_K_BYTES] {
+        let mut b77 = [0u8; FTX_LDPC_K_BYTES];
+        pack77(msg, hash_table, &mut b77).unwrap_or_else(|e| panic!("cannot pack {}: {}", msg, e));
+        let mut text = String::new();
+        unpack77(&b77, hash_table, &mut text).unwrap();
+        assert_eq!(text, msg);
+        b77
+    }
+
+    fn i3(b77: &[u8; FTX_LDPC_K_BYTES]) -> u64 {
+        get_bits(b77, 74, 3)
+    }
+
+    fn n3(b77: &[u8; FTX_LDPC_K_BYTES]) -> u64 {
+        get_bits(b77, 71, 3)
+    }
+
+    #[test]
+    fn free_text_roundtrip() {
+        let hash_table = CallsignHashTable::new();
+        for msg in ["TNX BOB 73 GL", "HELLO WORLD", "A+B-C./?", "TNX"] {
+            let b77 = roundtrip(msg, &hash_table);
+            assert_eq!((i3(&b77), n3(&b77)), (0, 0));
+        }
+        // Two words that are not both callsigns are not a type 4 message
+        for msg in ["GL 73", "TU 73", "RR 73", "TNX 73", "CQ TEST"] {
+            let b77 = roundtrip(msg, &hash_table);
+            assert_eq!((i3(&b77), n3(&b77)), (0, 0));
+        }
+        assert!(matches!(
+            "THIS TEXT IS TOO LONG".parse::<Ft8Payload>(),
+            Err(Ft8Error::InvalidText(_))
+        ));
+    }
+
+    #[test]
+    fn typed_fields() {
+        let payload: Ft8Payload = "CQ DX K1ABC/R FN42".parse().unwrap();
+        assert_eq!(
+            payload,
+            Ft8Payload::Standard {
+                call_to: Call28::Cq(Some(CqModifier::Letters("DX".to_string()))),
+                suffix_to: false,
+                call_de: Call28::Standard("K1ABC".to_string()),
+                suffix_de: true,
+                portable: false,
+                ack: false,
+                extra: Extra::Grid("FN42".to_string()),
+            }
+        );
+        assert!(payload.is_cq());
+        assert_eq!(payload.grid(), Some("FN42"));
+
+        let payload: Ft8Payload = "K1ABC W9XYZ R-08".parse().unwrap();
+        assert!(!payload.is_cq());
+        assert_eq!(payload.report(), Some(-8));
+        assert_eq!(payload.callsigns(), ["K1ABC", "W9XYZ"]);
+    }
+
+    #[test]
+    fn typed_errors() {
+        let hash_table = CallsignHashTable::new();
+        let mut a77 = [0u8; FTX_LDPC_K_BYTES];
+        a77[9] = 0x38; // i3 = 7
+        assert!(matches!(
+            Ft8Payload::from_bytes(&a77, &hash_table),
+            Err(Ft8Error::UnsupportedMessageType { i3: 7, n3: 0 })
+        ));
+        assert!(matches!(
+            "K1ABC W9XYZ R JO22DY".parse::<Ft8Payload>(),
+            Err(Ft8Error::GridOutOfRange(_))
+        ));
+        assert!(matches!(
+            "K1ABC RR73; W9XYZ <KH1/KH7Z> -31".parse::<Ft8Payload>(),
+            Err(Ft8Error::ReportOutOfRange(_))
+        ));
+        assert!(matches!(
+            "W9XYZ K1ABC 33A EMA".parse::<Ft8Payload>(),
+            Err(Ft8Error::InvalidExchange(_))
+        ));
+    }
+
+    #[test]
+    fn bytes_are_lossless() {
+        // Every 77-bit pattern that unpacks must pack back to the same bits,
+        // even when its hashed callsigns are unknown.
+        let hash_table = CallsignHashTable::new();
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut decoded = 0;
+        for _ in 0..20000 {
+            let mut a77 = [0u8; FTX_LDPC_K_BYTES];
+            for b in a77.iter_mut().take(10) {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                *b = seed as u8;
+            }
+            a77[9] &= 0xF8;
+            if let Ok(payload) = Ft8Payload::from_bytes(&a77, &hash_table) {
+                assert_eq!(payload.to_bytes().ok(), Some(a77), "{:?}", payload);
+                decoded += 1;
+            }
+        }
+        assert!(decoded > 10000);
+
+        // The square RR73 (igrid4 = 32373) is a grid, not the reply
+        let mut a77 = [0u8; FTX_LDPC_K_BYTES];
+        pack77("K1ABC W9XYZ EN37", &hash_table, &mut a77).unwrap();
+        put_bits(&mut a77, 59, 15, 32373);
+        let payload = Ft8Payload::from_bytes(&a77, &hash_table).unwrap();
+        assert_eq!(payload.grid(), Some("RR73"));
+        assert_eq!(payload.to_bytes().ok(), Some(a77));
+    }
+}
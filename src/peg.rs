@@ -1,23 +1,24 @@
+use crate::error::Ft8Error;
+use crate::ldpc::LdpcCode;
 use crate::tanner::TannerGraph;
 
+// Progressive Edge-Growth (PEG) による検査行列の構成
+// symbolnode_degree[i]はビットノードiの次数(列重み)
 pub fn ldpc_peg(
     checknode_number: usize,
     symbolnode_number: usize,
     symbolnode_degree: Vec<usize>,
 ) -> Vec<Vec<u32>> {
+    assert_eq!(symbolnode_degree.len(), symbolnode_number);
+    assert!(symbolnode_degree.iter().all(|d| *d <= checknode_number));
+
     let paritycheck_matrix = vec![vec![0; symbolnode_number]; checknode_number];
 
     let mut tanner = TannerGraph::new(paritycheck_matrix);
-    print!("Symbol=");
     for (idx, degree) in symbolnode_degree.iter().enumerate() {
         //ビットノード毎に検査ノードへのエッジを張る
         let symbol_idx = tanner.get_symbolnode(idx);
 
-        print!("{} ", symbol_idx);
-        if symbol_idx % 20 == 0 {
-            println!()
-        };
-
         //ビットノードにつながるエッジの個数分繰り返し
         for i in 0..*degree {
             if i == 0 {
@@ -25,8 +26,6 @@ pub fn ldpc_peg(
                 //もっとも接続が少ない検査ノードと接続
                 let lowest = tanner.get_checknode_with_lowest_degree();
                 tanner.create_edge(symbol_idx, lowest);
-
-                tanner.output_matrix(symbol_idx);
             } else {
                 let mut depth = 0;
                 //現在のシンボルノードから幅優先探索で指定された
@@ -36,11 +35,9 @@ pub fn ldpc_peg(
                     //全ての検査ノードが包含されている場合
                     if current_subgraph.all_checknodes_covered() {
                         //一つ前の部分グラフで最も接続が少ない検査ノードと接続
-                        let previous = tanner.get_subgraph(symbol_idx, depth - 1);
+                        let previous = tanner.get_subgraph(symbol_idx, depth.saturating_sub(1));
                         let lowest = previous.get_uc_checknode_with_lowest_degree().unwrap();
                         tanner.create_edge(symbol_idx, lowest);
-                        tanner.output_matrix(symbol_idx);
-
                         break;
                     };
                     //深さを一段深くして部分グラフを作り
@@ -52,7 +49,6 @@ pub fn ldpc_peg(
                             .get_uc_checknode_with_lowest_degree()
                             .unwrap();
                         tanner.create_edge(symbol_idx, lowest);
-                        tanner.output_matrix(symbol_idx);
                         break;
                     };
                     //更に深く部分グラフを探索
@@ -61,8 +57,80 @@ pub fn ldpc_peg(
             }
         }
     }
-    println!();
-    tanner.output_edges();
 
     tanner.matrix
 }
+
+// Column degrees for n bits from a distribution such as [(2, 0.25), (3, 0.75)].
+// Low degrees come first, as PEG places them best that way.
+pub fn degree_sequence(n: usize, distribution: &[(usize, f32)]) -> Result<Vec<usize>, Ft8Error> {
+    let total: f32 = distribution.iter().map(|(_, f)| f).sum();
+    if distribution.is_empty() || (total - 1.0).abs() > 1e-3 {
+        return Err(Ft8Error::InvalidDistribution { sum: total });
+    }
+    let mut sorted = distribution.to_vec();
+    sorted.sort_by_key(|(d, _)| *d);
+
+    let mut degrees = Vec::with_capacity(n);
+    for (i, (degree, fraction)) in sorted.iter().enumerate() {
+        //端数は最後の次数に割り当てる
+        let count = if i + 1 == sorted.len() {
+            n - degrees.len()
+        } else {
+            ((fraction * n as f32).round() as usize).min(n - degrees.len())
+        };
+        degrees.extend(std::iter::repeat_n(*degree, count));
+    }
+    Ok(degrees)
+}
+
+// PEGで検査行列を作り、組織符号の生成行列を求める
+pub fn peg_code(n: usize, m: usize, distribution: &[(usize, f32)]) -> Result<LdpcCode, Ft8Error> {
+    let degrees = degree_sequence(n, distribution)?;
+    if let Some(&degree) = degrees.iter().find(|d| **d == 0 || **d > m) {
+        return Err(Ft8Error::InvalidDegree { degree, checks: m });
+    }
+    Ok(LdpcCode::from_parity_check(&ldpc_peg(m, n, degrees)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peg_builds_a_usable_code() {
+        assert_eq!(
+            degree_sequence(10, &[(3, 0.7), (2, 0.3)]).unwrap(),
+            vec![2, 2, 2, 3, 3, 3, 3, 3, 3, 3]
+        );
+        assert!(matches!(
+            degree_sequence(10, &[(3, 0.5)]),
+            Err(Ft8Error::InvalidDistribution { sum }) if sum == 0.5
+        ));
+        assert!(matches!(
+            peg_code(10, 2, &[(3, 1.0)]),
+            Err(Ft8Error::InvalidDegree { degree: 3, checks: 2 })
+        ));
+
+        let code = peg_code(48, 24, &[(2, 0.5), (3, 0.5)]).unwrap();
+        assert_eq!((code.n, code.m()), (48, 24));
+        for (bit, checks) in code.mn.iter().enumerate() {
+            assert!(checks.len() == 2 || checks.len() == 3, "{} {:?}", bit, checks);
+        }
+        // No two bits share more than one check (no 4-cycles)
+        for a in 0..code.n {
+            for b in a + 1..code.n {
+                let shared = code.mn[a].iter().filter(|c| code.mn[b].contains(c)).count();
+                assert!(shared <= 1, "{} {}", a, b);
+            }
+        }
+
+        let message: Vec<u8> = (0..code.k_bytes() as u8).map(|i| 0xa5 ^ i).collect();
+        let mut codeword = vec![0u8; code.n_bytes()];
+        code.encode(&message, &mut codeword);
+        let bits: Vec<u8> = (0..code.n)
+            .map(|i| (codeword[i / 8] >> (7 - i % 8)) & 1)
+            .collect();
+        assert_eq!(code.check(&bits), 0);
+    }
+}
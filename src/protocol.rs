@@ -0,0 +1,120 @@
+use crate::constant::*;
+use crate::gfsk::{FT4_SYMBOL_BT, FT8_SYMBOL_BT};
+use std::ops::Range;
+
+// Description of an FTx mode. Encoder, Monitor and decoder only look at this,
+// so a new mode needs a new descriptor rather than new code paths.
+#[derive(Debug, PartialEq)]
+pub struct Protocol {
+    pub name: &'static str,
+    pub marker: char,       // Mode character in WSJT-X decode lines
+    pub symbol_period: f32, // Symbol period (s)
+    pub slot_time: f32,     // Slot length (s)
+    pub tx_start: f32,      // Start of the transmission in the slot (s), DT is measured from here
+    pub symbol_bt: f32,     // GFSK bandwidth-time product
+    pub num_tones: usize,   // Number of FSK tones
+    pub num_symbols: usize, // Total channel symbols
+    pub num_data_symbols: usize,
+    pub num_ramp: usize, // Ramp symbols at each end of the frame
+    // First symbol of each sync group and its Costas array
+    pub sync: &'static [(usize, &'static [usize])],
+    pub gray_map: &'static [usize],    // Gray code map (bits -> tone)
+    pub payload_xor: &'static [u8],    // Scrambling sequence for the 77-bit payload
+    pub time_offset_range: Range<i32>, // Blocks searched for sync
+    pub decode_passes: &'static [f32], // Default decode times in the slot (s)
+}
+
+pub static FT8: Protocol = Protocol {
+    name: "FT8",
+    marker: '~',
+    symbol_period: FT8_SYMBOL_PERIOD,
+    slot_time: FT8_SLOT_TIME,
+    tx_start: 0.5,
+    symbol_bt: FT8_SYMBOL_BT,
+    num_tones: 8,
+    num_symbols: FT8_NN,
+    num_data_symbols: FT8_ND,
+    num_ramp: 0,
+    sync: &[
+        (0, &FT8_COSTAS_PATTERN),
+        (FT8_SYNC_OFFSET, &FT8_COSTAS_PATTERN),
+        (2 * FT8_SYNC_OFFSET, &FT8_COSTAS_PATTERN),
+    ],
+    gray_map: &FT8_GRAY_MAP,
+    payload_xor: &[],
+    // -1.92s から 3.84s まで
+    time_offset_range: -12..24,
+    // WSJT-Xと同じく早期デコードを2回行う
+    decode_passes: &[11.8, 13.5, 15.0],
+};
+
+pub static FT4: Protocol = Protocol {
+    name: "FT4",
+    marker: '+',
+    symbol_period: FT4_SYMBOL_PERIOD,
+    slot_time: FT4_SLOT_TIME,
+    tx_start: 0.5,
+    symbol_bt: FT4_SYMBOL_BT,
+    num_tones: 4,
+    num_symbols: FT4_NN,
+    num_data_symbols: FT4_ND,
+    num_ramp: 1,
+    sync: &[
+        (1, &FT4_COSTAS_PATTERN[0]),
+        (1 + FT4_SYNC_OFFSET, &FT4_COSTAS_PATTERN[1]),
+        (1 + 2 * FT4_SYNC_OFFSET, &FT4_COSTAS_PATTERN[2]),
+        (1 + 3 * FT4_SYNC_OFFSET, &FT4_COSTAS_PATTERN[3]),
+    ],
+    gray_map: &FT4_GRAY_MAP,
+    payload_xor: &FT4_XOR_SEQUENCE,
+    // FT8と同じ秒数
+    time_offset_range: -40..80,
+    decode_passes: &[7.5],
+};
+
+impl Protocol {
+    // Bits carried by one data symbol
+    pub fn bits_per_symbol(&self) -> usize {
+        self.num_tones.trailing_zeros() as usize
+    }
+
+    // Costas tone if the symbol belongs to a sync group
+    pub fn sync_tone(&self, sym_idx: usize) -> Option<usize> {
+        self.sync.iter().find_map(|(start, pattern)| {
+            sym_idx
+                .checked_sub(*start)
+                .and_then(|k| pattern.get(k).copied())
+        })
+    }
+
+    pub fn is_ramp(&self, sym_idx: usize) -> bool {
+        sym_idx < self.num_ramp || sym_idx >= self.num_symbols - self.num_ramp
+    }
+
+    // Channel symbol positions of the data symbols, in order
+    pub fn data_symbols(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.num_symbols).filter(|&i| !self.is_ramp(i) && self.sync_tone(i).is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_carry_one_codeword() {
+        for protocol in [&FT8, &FT4] {
+            assert_eq!(protocol.data_symbols().count(), protocol.num_data_symbols);
+            assert_eq!(
+                protocol.num_data_symbols * protocol.bits_per_symbol(),
+                FTX_LDPC_N
+            );
+        }
+        // FT8: S7 D29 S7 D29 S7
+        let ft8: Vec<usize> = FT8.data_symbols().collect();
+        assert_eq!((ft8[0], ft8[28], ft8[29], ft8[57]), (7, 35, 43, 71));
+        // FT4: R S4 D29 S4 D29 S4 D29 S4 R
+        let ft4: Vec<usize> = FT4.data_symbols().collect();
+        assert_eq!((ft4[0], ft4[29], ft4[58], ft4[86]), (5, 38, 71, 99));
+    }
+}
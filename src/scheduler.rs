@@ -0,0 +1,376 @@
+use crate::constant::FTX_LDPC_K_BYTES;
+use crate::error::Ft8Error;
+use crate::ft8encode::ftx_encode;
+use crate::gfsk::GfskModulator;
+use crate::hashtable::CallsignHashTable;
+use crate::pack::pack77;
+use crate::protocol::Protocol;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Sink writes per second (40 ms blocks at any sample rate)
+const TX_BLOCKS_PER_SECOND: u32 = 25;
+
+// Destination of the transmit audio (sound card, file, ...)
+pub trait AudioSink {
+    // Called when a transmission starts at the given UTC time (s since the epoch)
+    fn begin(&mut self, _start: f64) -> Result<(), Ft8Error> {
+        Ok(())
+    }
+    // Sound card sinks are expected to block until the samples are played
+    fn write(&mut self, samples: &[f32]) -> Result<(), Ft8Error>;
+    fn end(&mut self) -> Result<(), Ft8Error> {
+        Ok(())
+    }
+}
+
+// Raw little-endian f32 samples to a file or stdout
+// e.g. `| aplay -f FLOAT_LE -r 12000`
+pub struct StreamSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> StreamSink<W> {
+    pub fn new(out: W) -> Self {
+        StreamSink { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl StreamSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        StreamSink::new(io::stdout())
+    }
+}
+
+impl<W: Write> AudioSink for StreamSink<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<(), Ft8Error> {
+        for s in samples {
+            self.out.write_all(&s.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), Ft8Error> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+// UTC time source, replaceable for tests
+pub trait Clock {
+    // Seconds since the epoch
+    fn now(&self) -> f64;
+    fn sleep_until(&self, t: f64);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64())
+    }
+
+    fn sleep_until(&self, t: f64) {
+        let wait = t - self.now();
+        if wait > 0.0 {
+            thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
+
+// Which slots to transmit in. Even slots start at 00 and 30 s for FT8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parity {
+    Even,
+    Odd,
+    Any,
+}
+
+// A queued message, encoded when it is queued
+#[derive(Debug, Clone)]
+pub struct QueuedTx {
+    pub message: String,
+    pub freq_hz: f32, // Audio frequency of tone 0
+    tones: Vec<usize>,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<QueuedTx>>,
+    enabled: AtomicBool,
+}
+
+// 他のスレッドから送信を制御するハンドル
+#[derive(Clone)]
+pub struct TxControl {
+    protocol: &'static Protocol,
+    shared: Arc<Shared>,
+}
+
+impl TxControl {
+    // Pack and encode the message and add it to the queue
+    pub fn enqueue(&self, message: &str, freq_hz: f32) -> Result<(), Ft8Error> {
+        let mut packed = [0u8; FTX_LDPC_K_BYTES];
+        pack77(message, &CallsignHashTable::new(), &mut packed)?;
+        let mut tones = vec![0usize; self.protocol.num_symbols];
+        ftx_encode(self.protocol, &packed, &mut tones);
+        self.queue().push_back(QueuedTx {
+            message: message.to_string(),
+            freq_hz,
+            tones,
+        });
+        Ok(())
+    }
+
+    // Transmit the queue from the next matching slot on
+    pub fn tx_next_slot(&self) {
+        self.shared.enabled.store(true, Ordering::SeqCst);
+    }
+
+    // Stop transmitting. A transmission in progress ramps down over one symbol.
+    // The queue is kept.
+    pub fn halt(&self) {
+        self.shared.enabled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.shared.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue().len()
+    }
+
+    pub fn clear(&self) {
+        self.queue().clear();
+    }
+
+    fn queue(&self) -> std::sync::MutexGuard<'_, VecDeque<QueuedTx>> {
+        self.shared
+            .queue
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// UTCのスロットに合わせてキューのメッセージを送信する
+pub struct TxScheduler<S: AudioSink> {
+    protocol: &'static Protocol,
+    sample_rate: u32,
+    pub parity: Parity,
+    sink: S,
+    clock: Box<dyn Clock + Send>,
+    control: TxControl,
+    modulator: GfskModulator,
+}
+
+impl<S: AudioSink> TxScheduler<S> {
+    pub fn new(protocol: &'static Protocol, sample_rate: u32, parity: Parity, sink: S) -> Self {
+        TxScheduler {
+            protocol,
+            sample_rate,
+            parity,
+            sink,
+            clock: Box::new(SystemClock),
+            control: TxControl {
+                protocol,
+                shared: Arc::new(Shared {
+                    queue: Mutex::new(VecDeque::new()),
+                    enabled: AtomicBool::new(false),
+                }),
+            },
+            modulator: GfskModulator::new(
+                protocol.symbol_bt,
+                protocol.symbol_period,
+                sample_rate as f32,
+            ),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Box<dyn Clock + Send>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn control(&self) -> TxControl {
+        self.control.clone()
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Samples per sink write
+    fn block_len(&self) -> usize {
+        (self.sample_rate / TX_BLOCKS_PER_SECOND).max(1) as usize
+    }
+
+    // Audio start time (slot start + 0.5 s) of the next matching slot at or after now
+    pub fn next_start(&self, now: f64) -> f64 {
+        let slot_time = self.protocol.slot_time as f64;
+        let tx_start = self.protocol.tx_start as f64;
+        let mut slot = ((now - tx_start) / slot_time).ceil() as i64;
+        match self.parity {
+            Parity::Even if slot % 2 != 0 => slot += 1,
+            Parity::Odd if slot % 2 == 0 => slot += 1,
+            _ => (),
+        }
+        slot as f64 * slot_time + tx_start
+    }
+
+    // Wait for the next matching slot and transmit the first queued message.
+    // Returns the message sent, or None if TX is halted or the queue is empty.
+    // A halt() during the transmission ends it with a one-symbol ramp-down.
+    pub fn run_slot(&mut self) -> Result<Option<QueuedTx>, Ft8Error> {
+        let start = self.next_start(self.clock.now());
+        self.clock.sleep_until(start);
+        if !self.control.is_enabled() {
+            return Ok(None);
+        }
+        let tx = match self.control.queue().pop_front() {
+            Some(tx) => tx,
+            None => return Ok(None),
+        };
+
+        self.modulator.start(&tx.tones, tx.freq_hz, 0.0);
+        self.sink.begin(start)?;
+        let mut block = vec![0.0f32; self.block_len()];
+        while !self.modulator.is_done() {
+            if !self.control.is_enabled() {
+                self.modulator.stop();
+            }
+            let n = self.modulator.fill(&mut block);
+            self.sink.write(&block[..n])?;
+        }
+        self.sink.end()?;
+        Ok(Some(tx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{FT4, FT8};
+    use std::sync::atomic::AtomicU64;
+
+    // Clock that jumps to the requested time instead of sleeping
+    struct TestClock(Arc<AtomicU64>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> f64 {
+            f64::from_bits(self.0.load(Ordering::SeqCst))
+        }
+        fn sleep_until(&self, t: f64) {
+            if t > self.now() {
+                self.0.store(t.to_bits(), Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Records the start times and sample counts, halting after a number of writes
+    #[derive(Default)]
+    struct RecordingSink {
+        starts: Vec<f64>,
+        samples: usize,
+        halt_after: Option<(usize, TxControl)>,
+    }
+
+    impl AudioSink for RecordingSink {
+        fn begin(&mut self, start: f64) -> Result<(), Ft8Error> {
+            self.starts.push(start);
+            Ok(())
+        }
+        fn write(&mut self, samples: &[f32]) -> Result<(), Ft8Error> {
+            self.samples += samples.len();
+            if let Some((n, control)) = &mut self.halt_after {
+                *n -= 1;
+                if *n == 0 {
+                    control.halt();
+                    self.halt_after = None;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn scheduler(parity: Parity, time: &Arc<AtomicU64>) -> TxScheduler<RecordingSink> {
+        TxScheduler::new(&FT8, 12000, parity, RecordingSink::default())
+            .with_clock(Box::new(TestClock(time.clone())))
+    }
+
+    // 10 s into an even FT8 slot
+    const NOW: f64 = 1_700_000_110.0;
+
+    #[test]
+    fn slots_follow_parity() {
+        let time = Arc::new(AtomicU64::new(NOW.to_bits()));
+        assert_eq!(scheduler(Parity::Even, &time).next_start(NOW), NOW + 20.5);
+        assert_eq!(scheduler(Parity::Odd, &time).next_start(NOW), NOW + 5.5);
+        assert_eq!(scheduler(Parity::Any, &time).next_start(NOW), NOW + 5.5);
+        assert_eq!(
+            scheduler(Parity::Any, &time).next_start(NOW - 10.0),
+            NOW - 9.5
+        );
+
+        // FT4 slots are 7.5 s long
+        let ft4 = TxScheduler::new(&FT4, 12000, Parity::Odd, StreamSink::new(Vec::new()));
+        assert_eq!(ft4.next_start(NOW), NOW + 13.0);
+    }
+
+    #[test]
+    fn queue_and_halt() {
+        let time = Arc::new(AtomicU64::new(NOW.to_bits()));
+        let mut tx = scheduler(Parity::Even, &time);
+        let control = tx.control();
+        control.enqueue("CQ JA1ZLO PM95", 1500.0).unwrap();
+        control.enqueue("JA1ZLO JH1ABC -10", 1500.0).unwrap();
+        assert!(control.enqueue("<> ???", 1500.0).is_err());
+
+        // Nothing is sent until TX is enabled
+        assert!(tx.run_slot().unwrap().is_none());
+        assert_eq!(control.pending(), 2);
+
+        time.store((NOW + 25.0).to_bits(), Ordering::SeqCst);
+        control.tx_next_slot();
+        let sent = tx.run_slot().unwrap().unwrap();
+        assert_eq!(sent.message, "CQ JA1ZLO PM95");
+        assert_eq!(tx.sink().samples, 79 * 1920);
+        assert_eq!(tx.sink().starts, vec![NOW + 50.5]);
+
+        // halt() during the second transmission stops it one symbol after three blocks
+        time.store((NOW + 65.0).to_bits(), Ordering::SeqCst);
+        tx.sink.halt_after = Some((3, control.clone()));
+        let sent = tx.run_slot().unwrap().unwrap();
+        assert_eq!(sent.message, "JA1ZLO JH1ABC -10");
+        assert_eq!(tx.block_len(), 480);
+        let tx48k = TxScheduler::new(&FT8, 48000, Parity::Any, RecordingSink::default());
+        assert_eq!(tx48k.block_len(), 1920);
+        assert_eq!(tx.sink().samples, 79 * 1920 + 3 * 480 + 1920);
+        assert_eq!(tx.sink().starts[1], NOW + 80.5);
+        assert!(!control.is_enabled());
+        assert_eq!(control.pending(), 0);
+    }
+
+    #[test]
+    fn stream_sink_writes_raw_f32() {
+        let mut sink = StreamSink::new(Vec::new());
+        sink.write(&[0.5, -1.0]).unwrap();
+        sink.end().unwrap();
+        let bytes = sink.into_inner();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(&bytes[4..], &(-1.0f32).to_le_bytes());
+    }
+}
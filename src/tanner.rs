@@ -1,4 +1,7 @@
-use std::{collections::{HashMap, HashSet}};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::ldpc::LdpcCode;
 
 #[derive(Debug)]
 pub enum NodeType {
@@ -7,7 +10,6 @@ pub enum NodeType {
 }
 
 #[derive(Debug)]
-
 pub struct TannerGraph {
     pub matrix: Vec<Vec<u32>>,
     nodes: Vec<NodeType>,
@@ -16,31 +18,33 @@ pub struct TannerGraph {
 }
 
 impl<'a> TannerGraph {
-    pub fn add_edge(h:&mut HashMap<usize,Vec<usize>>,k:usize, v:usize) {
-         let Some(vl) = h.get_mut(&k)else {panic!("Node not found {}",k)};
-         vl.push(v);
+    pub fn add_edge(h: &mut HashMap<usize, Vec<usize>>, k: usize, v: usize) {
+        let Some(vl) = h.get_mut(&k) else {
+            panic!("Node not found {}", k)
+        };
+        vl.push(v);
     }
 
     pub fn new(matrix: Vec<Vec<u32>>) -> TannerGraph {
         let mut nodes = vec![];
-        let mut edges = HashMap::<usize,Vec<usize>>::new();
+        let mut edges = HashMap::<usize, Vec<usize>>::new();
         let offset = matrix[0].len();
         let checknode_len = matrix.len();
 
         matrix[0].iter().enumerate().for_each(|(index, _)| {
             nodes.push(NodeType::SymbolNode(index));
-            edges.insert(index,vec![]);
+            edges.insert(index, vec![]);
         });
 
         matrix.iter().enumerate().for_each(|(index, _)| {
             nodes.push(NodeType::CheckNode(index));
-            edges.insert(index + offset,vec![]);
+            edges.insert(index + offset, vec![]);
         });
 
         for (i, _) in matrix.iter().enumerate() {
             for (j, _) in matrix[i].iter().enumerate() {
                 if matrix[i][j] == 1 {
-                    TannerGraph::add_edge(&mut edges, i+ offset, j);
+                    TannerGraph::add_edge(&mut edges, i + offset, j);
                     TannerGraph::add_edge(&mut edges, j, i + offset);
                 }
             }
@@ -54,16 +58,20 @@ impl<'a> TannerGraph {
     }
 
     pub fn is_checknode(&self, node_id: usize) -> bool {
-      matches!(self.nodes[node_id], NodeType::CheckNode(_))
+        matches!(self.nodes[node_id], NodeType::CheckNode(_))
     }
 
-    pub fn get_symbolnode(&self, idx:usize) -> usize {
-        let NodeType::SymbolNode(i) = self.nodes[idx] else { panic!("Node not found {}",idx)};
+    pub fn get_symbolnode(&self, idx: usize) -> usize {
+        let NodeType::SymbolNode(i) = self.nodes[idx] else {
+            panic!("Node not found {}", idx)
+        };
         i
     }
-    
+
     pub fn checknode_edge_len(&self, idx: usize) -> usize {
-        let Some(e) = self.edges.get(&idx) else { panic!("Node not found  {}",idx)};
+        let Some(e) = self.edges.get(&idx) else {
+            panic!("Node not found  {}", idx)
+        };
         e.len()
     }
 
@@ -88,121 +96,310 @@ impl<'a> TannerGraph {
     }
 
     pub fn create_edge(&mut self, symbol_idx: usize, check_idx: usize) {
-        
         TannerGraph::add_edge(&mut self.edges, check_idx, symbol_idx);
         TannerGraph::add_edge(&mut self.edges, symbol_idx, check_idx);
-        
-        let NodeType::CheckNode(check_id)  = self.nodes[check_idx] else {panic!("Check node not found {}",check_idx)};
-        let NodeType::SymbolNode(symbol_id)  = self.nodes[symbol_idx] else {panic!("Symbol node not found {}",symbol_idx);};
+
+        let NodeType::CheckNode(check_id) = self.nodes[check_idx] else {
+            panic!("Check node not found {}", check_idx)
+        };
+        let NodeType::SymbolNode(symbol_id) = self.nodes[symbol_idx] else {
+            panic!("Symbol node not found {}", symbol_idx);
+        };
         self.matrix[check_id][symbol_id] = 1;
     }
 
     pub fn neighbors(&self, idx: usize) -> Vec<usize> {
-        let Some(v) = self.edges.get(&idx) else {panic!("Node not found {}",idx)};
-        v.clone()
+        self.adjacent(idx).to_vec()
     }
 
-    pub fn get_subgraph(&'a self, symbol_idx: usize, level: usize) -> SubGraph {
+    fn adjacent(&self, idx: usize) -> &[usize] {
+        let Some(v) = self.edges.get(&idx) else {
+            panic!("Node not found {}", idx)
+        };
+        v
+    }
+
+    pub fn get_subgraph(&'a self, symbol_idx: usize, level: usize) -> SubGraph<'a> {
         SubGraph::new(self, symbol_idx, level)
     }
+}
+
+// Small (a,b) trapping set: a symbol nodes whose induced subgraph has b odd-degree check nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrappingSet {
+    pub symbols: Vec<usize>,
+    pub unsatisfied: usize,
+}
+
+// 符号の構造解析の結果。エラーフロアの説明に使う
+#[derive(Debug)]
+pub struct GraphReport {
+    pub girth: Option<usize>,
+    pub local_girth: BTreeMap<usize, usize>, // local girth -> number of symbol nodes
+    pub acyclic_symbols: usize,              // symbol nodes on no cycle
+    pub cycles: Vec<(usize, usize)>,         // (length, count) for 4, 6 and 8
+    pub trapping_sets: Vec<TrappingSet>,     // sorted by (b, a)
+}
+
+impl TannerGraph {
+    pub fn from_code(code: &LdpcCode) -> TannerGraph {
+        let matrix = code
+            .nm
+            .iter()
+            .map(|row| (0..code.n).map(|i| row.contains(&i) as u32).collect())
+            .collect();
+        TannerGraph::new(matrix)
+    }
+
+    pub fn symbolnode_len(&self) -> usize {
+        self.nodes.len() - self.checknode_len
+    }
 
-    pub fn output_matrix(&self, nodeid: usize) {
-        if cfg!(debug_assertions) {
-            println!("Symbol({:?}) ", nodeid);
-            self.matrix.iter().for_each(|m| println!("{:?}", m));
-            println!("----------------------------")
+    // Length of the shortest cycle through the node, None if it is on no cycle
+    pub fn local_girth(&self, node: usize) -> Option<usize> {
+        SubGraph::until_cycle(self, node).shortest_cycle
+    }
+
+    // Every cycle goes through a symbol node
+    pub fn girth(&self) -> Option<usize> {
+        (0..self.symbolnode_len())
+            .filter_map(|s| self.local_girth(s))
+            .min()
+    }
+
+    // All simple cycles up to max_len edges, each once, as node lists starting at their
+    // lowest symbol node
+    pub fn cycles(&self, max_len: usize) -> Vec<Vec<usize>> {
+        let n = self.symbolnode_len();
+        let mut cycles = Vec::new();
+        let mut on_path = vec![false; self.nodes.len()];
+        for s in 0..n {
+            // max_len以下のサイクルはsから max_len/2 段以内に収まる
+            let sub = self.get_subgraph(s, (max_len / 2).saturating_sub(1));
+            let mut path = vec![s];
+            on_path[s] = true;
+            self.extend_cycle(&sub, max_len, &mut path, &mut on_path, &mut cycles);
+            on_path[s] = false;
         }
+        cycles
     }
 
-    pub fn output_edges(&self) {
-        println!("Result=");
-        self.output_matrix(0);
+    fn extend_cycle(
+        &self,
+        sub: &SubGraph,
+        max_len: usize,
+        path: &mut Vec<usize>,
+        on_path: &mut [bool],
+        cycles: &mut Vec<Vec<usize>>,
+    ) {
+        let n = self.symbolnode_len();
+        let start = path[0];
+        let last = *path.last().unwrap();
+        for &v in self.adjacent(last) {
+            // 閉じる向きは2通りあるので2番目のノードが最後のノードより小さいものだけ数える
+            if v == start && path.len() >= 4 && path[1] < last {
+                cycles.push(path.clone());
+            }
+            if on_path[v] || (v < n && v < start) {
+                continue;
+            }
+            // vから始点に戻るにはsub上の深さ分の辺が要る
+            match sub.depth(v) {
+                Some(d) if path.len() + d <= max_len => {}
+                _ => continue,
+            }
+            on_path[v] = true;
+            path.push(v);
+            self.extend_cycle(sub, max_len, path, on_path, cycles);
+            path.pop();
+            on_path[v] = false;
+        }
+    }
 
-        self.nodes.iter().enumerate()
-        .for_each(|(idx, n)| {
-            if let NodeType::SymbolNode(i) = n {
-                print!("Symbol({:?}) ->", i);
-                self.neighbors(idx).iter().for_each(|chk| {
-                    let NodeType::CheckNode(idx) = self.nodes[*chk] else { panic!("Check node not found {}",*chk)};
-                    print!("Check({:?}) ", idx)
-                });
-                println!()
+    // Number of check nodes connected an odd number of times to the symbol nodes
+    pub fn unsatisfied_checks(&self, symbols: &[usize]) -> usize {
+        let mut count = HashMap::<usize, usize>::new();
+        for s in symbols {
+            for c in self.neighbors(*s) {
+                *count.entry(c).or_default() += 1;
             }
+        }
+        count.values().filter(|c| *c % 2 == 1).count()
+    }
+
+    // Trapping set candidates: symbol nodes of the cycles up to max_len with at most
+    // max_unsatisfied odd-degree checks
+    pub fn trapping_sets(&self, max_len: usize, max_unsatisfied: usize) -> Vec<TrappingSet> {
+        let n = self.symbolnode_len();
+        let sets: HashSet<Vec<usize>> = self
+            .cycles(max_len)
+            .into_iter()
+            .map(|cycle| {
+                let mut symbols: Vec<usize> = cycle.into_iter().filter(|v| *v < n).collect();
+                symbols.sort();
+                symbols
+            })
+            .collect();
+        let mut result: Vec<TrappingSet> = sets
+            .into_iter()
+            .map(|symbols| TrappingSet {
+                unsatisfied: self.unsatisfied_checks(&symbols),
+                symbols,
+            })
+            .filter(|t| t.unsatisfied <= max_unsatisfied)
+            .collect();
+        result.sort_by(|x, y| {
+            (x.unsatisfied, x.symbols.len(), &x.symbols).cmp(&(
+                y.unsatisfied,
+                y.symbols.len(),
+                &y.symbols,
+            ))
         });
+        result
+    }
+
+    pub fn report(&self) -> GraphReport {
+        let mut local_girth = BTreeMap::new();
+        let mut acyclic_symbols = 0;
+        for s in 0..self.symbolnode_len() {
+            match self.local_girth(s) {
+                Some(g) => *local_girth.entry(g).or_insert(0) += 1,
+                None => acyclic_symbols += 1,
+            }
+        }
+        let cycles = self.cycles(8);
+        GraphReport {
+            girth: local_girth.keys().next().copied(),
+            local_girth,
+            acyclic_symbols,
+            cycles: [4, 6, 8]
+                .iter()
+                .map(|len| (*len, cycles.iter().filter(|c| c.len() == *len).count()))
+                .collect(),
+            // (a,b) with b no larger than a
+            trapping_sets: self
+                .trapping_sets(8, 4)
+                .into_iter()
+                .filter(|t| t.unsatisfied <= t.symbols.len())
+                .collect(),
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct RootNode {
-    root_idx: usize,
-    children: Vec<RootNode>,
+impl fmt::Display for GraphReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.girth {
+            Some(g) => writeln!(f, "Girth: {}", g)?,
+            None => writeln!(f, "Girth: none (no cycles)")?,
+        }
+        writeln!(f, "Local girth:")?;
+        for (g, count) in self.local_girth.iter() {
+            writeln!(f, "  {:3}: {}", g, count)?;
+        }
+        if self.acyclic_symbols > 0 {
+            writeln!(f, "  none: {}", self.acyclic_symbols)?;
+        }
+        writeln!(f, "Cycles:")?;
+        for (len, count) in self.cycles.iter() {
+            writeln!(f, "  {}-cycles: {}", len, count)?;
+        }
+        writeln!(
+            f,
+            "Trapping set candidates (a,b): {}",
+            self.trapping_sets.len()
+        )?;
+        for t in self.trapping_sets.iter().take(20) {
+            writeln!(
+                f,
+                "  ({},{}) {:?}",
+                t.symbols.len(),
+                t.unsatisfied,
+                t.symbols
+            )?;
+        }
+        Ok(())
+    }
 }
 
+// Nodes reachable from a symbol node within a number of hops (breadth first)
 pub struct SubGraph<'a> {
     tanner: &'a TannerGraph,
     pub level: usize,
-    tree_root: RootNode,
+    reached: HashMap<usize, (usize, usize)>, // node -> (hops from the root, first node after the root)
+    pub shortest_cycle: Option<usize>,       // Shortest cycle through the root within the subgraph
 }
 
 impl<'a> SubGraph<'a> {
-    fn bfs_tanner(
-        tanner: &'a TannerGraph,
-        root_idx: usize,
-        mut level: usize,
-        depth: usize,
-        used: &mut HashSet<usize>,
-    ) -> (usize, Vec<RootNode>) {
-        level += 1;
-
-        let mut children_node: Vec<_> = tanner
-            .neighbors(root_idx)
-            .iter()
-            .filter(|c| !used.contains(*c))
-            .map(|n| RootNode {
-                root_idx: *n,
-                children: vec![],
-            })
-            .collect();
-
-        children_node.iter().for_each(|r| {
-            used.insert(r.root_idx);
-        });
+    // depth 0 は隣接する検査ノードだけ、depthが1増えるごとに1段先まで広げる
+    // levelは実際に新しいノードが見つかった段数で、グラフが広がらなくなると増えない
+    pub fn new(tanner: &'a TannerGraph, idx: usize, depth: usize) -> SubGraph<'a> {
+        Self::expand(tanner, idx, depth, false)
+    }
 
-        if level <= depth {
-            children_node.iter_mut().for_each(|r| {
-                (level, r.children) = Self::bfs_tanner(tanner, r.root_idx, level, depth, used)
-            });
-        };
-        (level, children_node)
+    // Expand until the shortest cycle through idx is found or the graph stops growing
+    pub fn until_cycle(tanner: &'a TannerGraph, idx: usize) -> SubGraph<'a> {
+        Self::expand(tanner, idx, usize::MAX, true)
     }
 
-    pub fn new(tanner: &'a TannerGraph, idx: usize, depth: usize) -> SubGraph<'a> {
-        let mut used = HashSet::<usize>::new();
-        let (level, children) = Self::bfs_tanner(tanner, idx, 0, depth, &mut used);
+    // 根のどの枝から来たかを覚え、異なる枝どうしをつなぐ辺でサイクルができる。
+    // 段ごとに広げるので、最初にサイクルが見つかった段で最短のものが求まる
+    fn expand(
+        tanner: &'a TannerGraph,
+        idx: usize,
+        depth: usize,
+        until_cycle: bool,
+    ) -> SubGraph<'a> {
+        let mut reached = HashMap::from([(idx, (0, idx))]);
+        let mut frontier = vec![idx];
+        let mut level = 0;
+        let mut shortest_cycle: Option<usize> = None;
+        for _ in 0..=depth {
+            let mut next = Vec::new();
+            for &u in frontier.iter() {
+                let (hops, branch) = reached[&u];
+                for &v in tanner.adjacent(u) {
+                    let branch = if u == idx { v } else { branch };
+                    match reached.get(&v) {
+                        None => {
+                            reached.insert(v, (hops + 1, branch));
+                            next.push(v);
+                        }
+                        Some(&(v_hops, v_branch)) if v != idx && v_branch != branch => {
+                            let len = hops + v_hops + 1;
+                            shortest_cycle = Some(shortest_cycle.map_or(len, |c| c.min(len)));
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            level += 1;
+            frontier = next;
+            if until_cycle && shortest_cycle.is_some() {
+                break;
+            }
+        }
         Self {
             tanner,
-            tree_root: RootNode {
-                root_idx: idx,
-                children,
-            },
             level,
+            reached,
+            shortest_cycle,
         }
     }
 
-    fn dig_root_node(&self, root: &RootNode, hash: &mut HashSet<usize>) {
-        root.children
-            .iter()
-            .for_each(|f| self.dig_root_node(f, hash));
-
-        if self.tanner.is_checknode(root.root_idx) {
-            hash.insert(root.root_idx);
-        };
+    // Hops from the root, None if the node is outside the subgraph
+    pub fn depth(&self, idx: usize) -> Option<usize> {
+        self.reached.get(&idx).map(|(hops, _)| *hops)
     }
 
     pub fn covered_checknodes(&self) -> HashSet<usize> {
-        let mut hash =  HashSet::<usize>::new();
-        self.dig_root_node(&self.tree_root, &mut hash);
-        hash
+        self.reached
+            .keys()
+            .filter(|n| self.tanner.is_checknode(**n))
+            .copied()
+            .collect()
     }
 
     pub fn all_checknodes_covered(&self) -> bool {
@@ -213,10 +410,51 @@ impl<'a> SubGraph<'a> {
         let covered = self.covered_checknodes();
         let uncovered = self.tanner.get_uncovered_checknodes(&covered);
 
-        let Some((idx, _)) = 
-        uncovered.iter().map(|i|(*i,self.tanner.checknode_edge_len(*i)))
-        .reduce(|(i,l),(j,m)| if l > m {(j,m)} else {(i,l)}) else { return None;};
+        let (idx, _) = uncovered
+            .iter()
+            .map(|i| (*i, self.tanner.checknode_edge_len(*i)))
+            .reduce(|(i, l), (j, m)| if l > m { (j, m) } else { (i, l) })?;
 
         Some(idx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ldpc::FTX_LDPC_CODE;
+
+    #[test]
+    fn small_graph_cycles() {
+        // Bits 0 and 1 share both checks: one 4-cycle that is also a codeword (2,0)
+        let tanner = TannerGraph::new(vec![vec![1, 1, 0, 1], vec![1, 1, 1, 0], vec![0, 0, 1, 1]]);
+        assert_eq!(tanner.girth(), Some(4));
+        assert_eq!(tanner.local_girth(0), Some(4));
+        assert_eq!(tanner.local_girth(2), Some(6));
+        let report = tanner.report();
+        assert_eq!(report.cycles, vec![(4, 1), (6, 2), (8, 0)]);
+        assert_eq!(
+            report.trapping_sets[0],
+            TrappingSet {
+                symbols: vec![0, 1],
+                unsatisfied: 0
+            }
+        );
+    }
+
+    #[test]
+    fn ft8_code_report() {
+        let tanner = TannerGraph::from_code(&FTX_LDPC_CODE);
+        let report = tanner.report();
+        // No 4- or 6-cycles in the FT8 code
+        assert_eq!(report.girth, Some(8));
+        assert_eq!(report.cycles[..2], [(4, 0), (6, 0)]);
+        assert!(report.cycles[2].1 > 0);
+        assert_eq!(report.acyclic_symbols, 0);
+        assert_eq!(report.local_girth.values().sum::<usize>(), 174);
+        assert!(report
+            .trapping_sets
+            .iter()
+            .all(|t| t.unsatisfied <= t.symbols.len()));
+    }
+}
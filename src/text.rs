@@ -1,14 +1,15 @@
 use std::fmt::Write;
 
-pub fn dd_to_int(str: &str) -> i32 {
-    str.parse().unwrap()
+pub fn dd_to_int(str: &str) -> Option<i32> {
+    str.parse().ok()
 }
 
+// Append a number with at least two digits, e.g. "-08" or "+12" with full_sign
 pub fn int_to_dd(mesg: &mut String, value: i32, full_sign: bool) {
     if full_sign {
-        let _ =write!(mesg, "{:+2}", value);
+        let _ = write!(mesg, "{:+03}", value);
     } else {
-        let _ = write!(mesg, "{:2}", value);
+        let _ = write!(mesg, "{:02}", value);
     }
 }
 
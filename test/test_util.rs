@@ -1,3 +1,5 @@
+use crate::constant::*;
+
 
 pub fn unpack_bits(codeward: &[u8; FTX_LDPC_N_BYTES], codeword_bits: &mut [u8; FTX_LDPC_N]) {
     let mut idx = 0;
@@ -5,9 +7,9 @@ pub fn unpack_bits(codeward: &[u8; FTX_LDPC_N_BYTES], codeword_bits: &mut [u8; F
         let mut c = *c;
         for _ in 0..8 {
             codeword_bits[idx] = if (0x80 & c) == 0 { 0 } else { 1 };
-            c = c << 1;
+            c <<= 1;
             idx += 1;
-            if idx >= 174 {
+            if idx >= FTX_LDPC_N {
                 break;
             }
         }